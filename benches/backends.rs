@@ -0,0 +1,41 @@
+//! Compare the subprocess and git2 backends by blaming this crate's own tree.
+//!
+//! Run with `cargo bench --features git2`, optionally passing a directory to
+//! blame instead: `cargo bench --features git2 -- /path/to/repo/src`.
+
+use blame::{Backend, BlameOptions, blame_paths};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 5;
+
+fn main() {
+    let target = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .unwrap_or_else(|| env!("CARGO_MANIFEST_DIR").to_string());
+    let patterns = vec![target.clone()];
+
+    println!("Blaming {} ({} iterations each)", target, ITERATIONS);
+    for (name, backend) in [("subprocess", Backend::Subprocess), ("git2", Backend::Git2)] {
+        let options = BlameOptions {
+            backend,
+            ..Default::default()
+        };
+
+        let mut total = Duration::ZERO;
+        let mut files = 0;
+        for _ in 0..ITERATIONS {
+            let start = Instant::now();
+            let report = blame_paths(&patterns, &options).expect("blame failed");
+            total += start.elapsed();
+            files = report.files.len();
+        }
+
+        println!(
+            "{:<12} {:>8.1?} per run ({} files)",
+            name,
+            total / ITERATIONS,
+            files
+        );
+    }
+}
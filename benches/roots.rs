@@ -0,0 +1,45 @@
+//! Time finding the repository root of every file in a directory, with and
+//! without `repository_root`'s per-directory cache.
+//!
+//! Run with `cargo bench --bench roots`. The files live in a scratch git
+//! repository under the system temp directory.
+
+use blame::vcs::{GitBackend, VcsBackend, repository_root};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const FILES: usize = 500;
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("blame-roots-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("could not create scratch directory");
+    let status = Command::new("git")
+        .args(["init", "--quiet"])
+        .current_dir(&dir)
+        .status()
+        .expect("could not run git");
+    assert!(status.success(), "git init failed");
+    let files: Vec<PathBuf> = (0..FILES)
+        .map(|i| {
+            let file = dir.join(format!("file{}.txt", i));
+            std::fs::write(&file, "line\n").expect("could not write scratch file");
+            file
+        })
+        .collect();
+
+    println!("Finding the root of {} files in one directory", FILES);
+    let time = |lookup: &dyn Fn(&PathBuf) -> Option<PathBuf>| -> Duration {
+        let start = Instant::now();
+        for file in &files {
+            lookup(file).expect("file is in the scratch repository");
+        }
+        start.elapsed()
+    };
+    let uncached = time(&|file| GitBackend.root(file));
+    let cached = time(&|file| repository_root(&GitBackend, file));
+    println!("{:<12} {:>8.1?}", "uncached", uncached);
+    println!("{:<12} {:>8.1?}", "cached", cached);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
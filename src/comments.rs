@@ -0,0 +1,127 @@
+//! Spotting lines that are nothing but comments, for
+//! [`BlameOptions::ignore_comments`](crate::BlameOptions::ignore_comments).
+//!
+//! This is a heuristic, not a parser. A line counts as a comment when, after
+//! leading whitespace, it starts with a line-comment marker, or it is inside a
+//! block comment that was opened at the start of a line. Block comments opened
+//! after code on the same line (`x = 1; /* ...`) aren't followed onto later
+//! lines, and comment markers inside string literals aren't recognized.
+
+use std::path::Path;
+
+/// How comments are written in one family of languages.
+pub(crate) struct CommentSyntax {
+    /// Markers that comment out the rest of the line.
+    line: &'static [&'static str],
+    /// Opening and closing markers of block comments.
+    block: &'static [(&'static str, &'static str)],
+}
+
+const C_LIKE: CommentSyntax = CommentSyntax {
+    line: &["//"],
+    block: &[("/*", "*/")],
+};
+const HASH: CommentSyntax = CommentSyntax {
+    line: &["#"],
+    block: &[],
+};
+const SQL: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("/*", "*/")],
+};
+const LUA: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("--[[", "]]")],
+};
+const HASKELL: CommentSyntax = CommentSyntax {
+    line: &["--"],
+    block: &[("{-", "-}")],
+};
+const LISP: CommentSyntax = CommentSyntax {
+    line: &[";"],
+    block: &[],
+};
+const PERCENT: CommentSyntax = CommentSyntax {
+    line: &["%"],
+    block: &[],
+};
+const CSS: CommentSyntax = CommentSyntax {
+    line: &[],
+    block: &[("/*", "*/")],
+};
+const MARKUP: CommentSyntax = CommentSyntax {
+    line: &[],
+    block: &[("<!--", "-->")],
+};
+const PHP: CommentSyntax = CommentSyntax {
+    line: &["//", "#"],
+    block: &[("/*", "*/")],
+};
+
+/// Comment syntax for `file`, by extension (or name, for the likes of
+/// `Makefile`). `None` for languages blame doesn't know.
+pub(crate) fn syntax_for(file: &Path) -> Option<&'static CommentSyntax> {
+    let name = file.file_name()?.to_str()?;
+    if matches!(name, "Makefile" | "Dockerfile" | "CMakeLists.txt") {
+        return Some(&HASH);
+    }
+    let extension = file.extension()?.to_str()?.to_ascii_lowercase();
+    let syntax = match extension.as_str() {
+        "rs" | "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "hh" | "java" | "js" | "jsx" | "mjs"
+        | "cjs" | "ts" | "tsx" | "go" | "swift" | "kt" | "kts" | "scala" | "cs" | "dart"
+        | "proto" | "zig" | "scss" | "less" | "groovy" | "gradle" => &C_LIKE,
+        "py" | "rb" | "sh" | "bash" | "zsh" | "fish" | "pl" | "pm" | "r" | "yaml" | "yml"
+        | "toml" | "cmake" | "mk" | "ex" | "exs" | "nix" | "ps1" | "tf" | "conf" => &HASH,
+        "sql" => &SQL,
+        "lua" => &LUA,
+        "hs" | "elm" => &HASKELL,
+        "lisp" | "el" | "clj" | "cljs" | "scm" | "asm" | "ini" => &LISP,
+        "tex" | "erl" | "hrl" => &PERCENT,
+        "css" => &CSS,
+        "html" | "htm" | "xml" | "svg" | "vue" => &MARKUP,
+        "php" => &PHP,
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Whether each of `lines`, a file's contents in order, is only comment.
+/// Blank lines count as comments only inside a block comment.
+pub(crate) fn comment_lines<'a>(
+    syntax: &CommentSyntax,
+    lines: impl IntoIterator<Item = &'a str>,
+) -> Vec<bool> {
+    // The closing marker of the block comment we're in, if any
+    let mut open_block: Option<&str> = None;
+    let mut comments = Vec::new();
+    'lines: for line in lines {
+        let mut rest = line.trim();
+        let mut saw_comment = open_block.is_some();
+        loop {
+            if let Some(close) = open_block {
+                let Some(end) = rest.find(close) else {
+                    comments.push(true);
+                    continue 'lines;
+                };
+                rest = rest[end + close.len()..].trim_start();
+                open_block = None;
+            }
+            if rest.is_empty() {
+                comments.push(saw_comment);
+                continue 'lines;
+            }
+            // Blocks first, as Lua's `--[[` also starts with its `--`
+            if let Some((open, close)) =
+                syntax.block.iter().find(|(open, _)| rest.starts_with(open))
+            {
+                rest = &rest[open.len()..];
+                open_block = Some(close);
+                saw_comment = true;
+                continue;
+            }
+            comments.push(syntax.line.iter().any(|marker| rest.starts_with(marker)));
+            continue 'lines;
+        }
+    }
+    comments
+}
@@ -0,0 +1,825 @@
+//! Resolving git authors to GitHub logins.
+
+use crate::vcs::output_with_timeout;
+use crate::{AuthorStats, git_command, rename_authors};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Program [`gh_command`] runs, once chosen by [`set_gh_program`].
+static GH_PROGRAM: OnceLock<OsString> = OnceLock::new();
+
+/// Run GitHub CLI commands with `program` instead of the first `gh` on `PATH`
+/// (or `$BLAME_GH`). Only the first call has any effect.
+pub fn set_gh_program(program: impl Into<OsString>) {
+    let _ = GH_PROGRAM.set(program.into());
+}
+
+/// A `gh` command, using the program from [`set_gh_program`] or `$BLAME_GH`
+/// when set.
+fn gh_command() -> Command {
+    let program = GH_PROGRAM.get_or_init(|| {
+        std::env::var_os("BLAME_GH")
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| "gh".into())
+    });
+    Command::new(program)
+}
+
+/// GitHub kept rate-limiting login lookups even after backing off, so some
+/// authors couldn't be resolved.
+#[derive(Debug, Error)]
+#[error(
+    "GitHub rate limit reached; some usernames not resolved \u{2014} try again {} or set GITHUB_TOKEN",
+    retry_time(.reset)
+)]
+pub struct RateLimited {
+    /// The logins that were resolved, keyed by author; the rest keep their
+    /// git names.
+    pub logins: HashMap<String, String>,
+    /// When the limit resets, if GitHub said.
+    pub reset: Option<DateTime<Utc>>,
+}
+
+/// "after 14:05" for a known reset time, in local time, or else "later".
+fn retry_time(reset: &Option<DateTime<Utc>>) -> String {
+    match reset {
+        Some(reset) => format!("after {}", reset.with_timezone(&Local).format("%H:%M")),
+        None => "later".to_string(),
+    }
+}
+
+/// A repository on github.com or a GitHub Enterprise host.
+pub struct GitHubRepo {
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+impl GitHubRepo {
+    /// Web page for a commit in this repository.
+    pub fn commit_url(&self, sha: &str) -> String {
+        format!(
+            "https://{}/{}/{}/commit/{}",
+            self.host, self.owner, self.repo, sha
+        )
+    }
+}
+
+/// On-disk cache of `owner/repo/sha -> login`. A commit's author login never
+/// changes, so entries are never invalidated.
+#[derive(Default)]
+pub struct LoginCache {
+    path: Option<PathBuf>,
+    entries: HashMap<String, String>,
+    dirty: bool,
+}
+
+impl LoginCache {
+    pub fn load() -> Self {
+        let Some(path) = login_cache_path() else {
+            return Self::default();
+        };
+        let entries = read_login_cache(&path);
+        LoginCache {
+            path: Some(path),
+            entries,
+            dirty: false,
+        }
+    }
+
+    fn key(repo: &GitHubRepo, sha: &str) -> String {
+        if repo.host == "github.com" {
+            format!("{}/{}/{}", repo.owner, repo.repo, sha)
+        } else {
+            format!("{}/{}/{}/{}", repo.host, repo.owner, repo.repo, sha)
+        }
+    }
+
+    pub fn get(&self, repo: &GitHubRepo, sha: &str) -> Option<&String> {
+        self.entries.get(&Self::key(repo, sha))
+    }
+
+    pub fn insert(&mut self, repo: &GitHubRepo, sha: &str, login: String) {
+        self.entries.insert(Self::key(repo, sha), login);
+        self.dirty = true;
+    }
+
+    /// Write the cache back to disk. Holds an exclusive lock while merging with
+    /// whatever is on disk, and writes through a temp file + rename so that
+    /// concurrent runs never see a half-written file.
+    pub fn save(&self) {
+        let Some(path) = &self.path else { return };
+        if !self.dirty {
+            return;
+        }
+        let _ = (|| -> std::io::Result<()> {
+            fs::create_dir_all(path.parent().unwrap())?;
+            let lock = File::create(path.with_extension("json.lock"))?;
+            lock.lock()?;
+
+            let mut entries = read_login_cache(path);
+            entries.extend(self.entries.clone());
+
+            let tmp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+            fs::write(&tmp_path, serde_json::to_string(&entries)?)?;
+            fs::rename(&tmp_path, path)
+        })();
+    }
+}
+
+/// The GitHub repository the remotes point at; see [`find_github_remote`].
+pub fn get_github_repo(git_root: &Path) -> Option<GitHubRepo> {
+    find_github_remote(git_root, None).map(|(_, repo)| repo)
+}
+
+/// The GitHub repository `remote` points at, along with the remote's name.
+/// Without a `remote`, picks among the remotes with GitHub URLs (on a host
+/// named like GitHub, if any are): `upstream` (the canonical repository in fork
+/// workflows), then `origin`, then the first one listed.
+pub fn find_github_remote(git_root: &Path, remote: Option<&str>) -> Option<(String, GitHubRepo)> {
+    let output = git_command()
+        .args(["remote", "-v"])
+        .current_dir(git_root)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    // Lines look like "origin\tgit@github.com:owner/repo.git (fetch)"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut remotes: Vec<(String, GitHubRepo)> = Vec::new();
+    for line in stdout.lines() {
+        let Some((name, rest)) = line.split_once('\t') else {
+            continue;
+        };
+        let url = rest.strip_suffix(" (fetch)").unwrap_or(rest);
+        if remotes.iter().any(|(seen, _)| seen == name) {
+            continue;
+        }
+        if let Some(mut repo) = parse_github_url(url) {
+            if is_ssh_url(url) {
+                repo.host = resolve_ssh_host(&repo.host);
+            }
+            remotes.push((name.to_string(), repo));
+        }
+    }
+
+    // Any host could be GitHub Enterprise, but one named like GitHub wins over
+    // e.g. a fork on GitLab
+    if remote.is_none() && remotes.iter().any(|(_, repo)| repo.host.contains("github")) {
+        remotes.retain(|(_, repo)| repo.host.contains("github"));
+    }
+    let position = match remote {
+        Some(remote) => remotes.iter().position(|(name, _)| name == remote)?,
+        None => ["upstream", "origin"]
+            .iter()
+            .find_map(|preferred| remotes.iter().position(|(name, _)| name == preferred))
+            .unwrap_or(0),
+    };
+    (position < remotes.len()).then(|| remotes.swap_remove(position))
+}
+
+/// Whether `url` is reached over SSH: `ssh://...` or scp-like `host:path`.
+fn is_ssh_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => scheme == "ssh" || scheme == "git+ssh",
+        None => url.contains(':'),
+    }
+}
+
+/// The real hostname behind `host` when it's an alias from `~/.ssh/config`,
+/// like `github-work` in `git@github-work:owner/repo.git`. An alias that isn't
+/// found there but can't be a real hostname either (no dots) is taken to mean
+/// github.com.
+fn resolve_ssh_host(host: &str) -> String {
+    let configured = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".ssh").join("config"))
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|config| ssh_config_hostname(&config, host));
+    match configured {
+        Some(hostname) => hostname,
+        None if !host.contains('.') && host != "localhost" => "github.com".to_string(),
+        None => host.to_string(),
+    }
+}
+
+/// The `HostName` `config` (in `ssh_config` format) gives for `alias`. Like
+/// ssh, the first matching `Host` section that sets one wins; `Match` and
+/// `Include` aren't supported.
+fn ssh_config_hostname(config: &str, alias: &str) -> Option<String> {
+    let mut matching = true;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((keyword, value)) = line.split_once(|c: char| c == '=' || c.is_whitespace())
+        else {
+            continue;
+        };
+        let value = value.trim_start_matches(|c: char| c == '=' || c.is_whitespace());
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                // A `!pattern` rules the host out even if another pattern matches
+                let patterns: Vec<&str> = value.split_whitespace().collect();
+                let matches =
+                    |pattern: &str| glob::Pattern::new(pattern).is_ok_and(|p| p.matches(alias));
+                matching = patterns.iter().any(|p| !p.starts_with('!') && matches(p))
+                    && !patterns
+                        .iter()
+                        .filter_map(|p| p.strip_prefix('!'))
+                        .any(matches);
+            }
+            "match" => matching = false,
+            "hostname" if matching => return Some(value.replace("%h", alias)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse GitHub (or GitHub Enterprise) remote URL formats:
+/// https://github.com/owner/repo.git
+/// https://github.ourcorp.com/owner/repo
+/// git@github.com:owner/repo.git
+/// ssh://git@github.ourcorp.com:22/owner/repo.git
+pub fn parse_github_url(url: &str) -> Option<GitHubRepo> {
+    let url = url.trim_end_matches('/').trim_end_matches(".git");
+
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let rest = rest.rsplit_once('@').map_or(rest, |(_, r)| r);
+        rest.split_once('/')?
+    } else {
+        // scp-like syntax: [user@]host:owner/repo
+        let rest = url.rsplit_once('@').map_or(url, |(_, r)| r);
+        rest.split_once(':')?
+    };
+    let host = host.split(':').next()?;
+
+    let parts: Vec<&str> = path.splitn(2, '/').collect();
+    if host.is_empty() || parts.len() != 2 || parts[0].is_empty() || parts[1].is_empty() {
+        return None;
+    }
+
+    Some(GitHubRepo {
+        host: host.to_string(),
+        owner: parts[0].to_string(),
+        repo: parts[1].to_string(),
+    })
+}
+
+/// Arguments that point `gh` at the right host; empty for github.com so the
+/// user's default host configuration still applies.
+fn gh_host_args(repo: &GitHubRepo) -> Vec<String> {
+    if repo.host == "github.com" {
+        vec![]
+    } else {
+        vec!["--hostname".to_string(), repo.host.clone()]
+    }
+}
+
+/// Most API lookups to run at once.
+const MAX_CONCURRENT_LOOKUPS: usize = 8;
+
+/// How many times to retry a request GitHub rate-limited, and how long to wait
+/// before the first retry; each wait is twice the last.
+const RATE_LIMIT_RETRIES: u32 = 4;
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest to wait on one HTTP request to the API.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a GitHub API request didn't produce a response.
+enum ApiError {
+    /// GitHub rate-limited the request, even after backing off. Carries when
+    /// the limit resets, if GitHub said.
+    RateLimited(Option<DateTime<Utc>>),
+    /// Anything else: not found, no access, no network, unreadable output.
+    Failed,
+}
+
+/// Token to call the API with directly over HTTP, from the same variables `gh`
+/// reads: `GH_TOKEN` or `GITHUB_TOKEN` for github.com, and
+/// `GH_ENTERPRISE_TOKEN` or `GITHUB_ENTERPRISE_TOKEN` for other hosts. Without
+/// one, requests go through `gh`.
+fn api_token(repo: &GitHubRepo) -> Option<String> {
+    let names = if repo.host == "github.com" {
+        ["GH_TOKEN", "GITHUB_TOKEN"]
+    } else {
+        ["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"]
+    };
+    names
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|token| token.to_string_lossy().trim().to_string())
+        .find(|token| !token.is_empty())
+}
+
+/// URL of API `path` on `repo`'s host, e.g. `https://api.github.com/user`.
+fn api_url(repo: &GitHubRepo, path: &str) -> String {
+    if repo.host == "github.com" {
+        format!("https://api.github.com/{}", path)
+    } else if path == "graphql" {
+        format!("https://{}/api/graphql", repo.host)
+    } else {
+        format!("https://{}/api/v3/{}", repo.host, path)
+    }
+}
+
+/// Shared so concurrent lookups reuse connections.
+fn http_agent() -> &'static ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        ureq::Agent::config_builder()
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .http_status_as_error(false)
+            .build()
+            .into()
+    })
+}
+
+/// Call API `path` (`graphql` to POST `graphql_body`, otherwise a GET),
+/// backing off and retrying while GitHub rate-limits it.
+fn api_request(
+    repo: &GitHubRepo,
+    path: &str,
+    graphql_body: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, ApiError> {
+    let token = api_token(repo);
+    let mut backoff = RATE_LIMIT_BACKOFF;
+    let mut retries = 0;
+    loop {
+        let result = match &token {
+            Some(token) => http_request(repo, token, path, graphql_body),
+            None => gh_request(repo, path, graphql_body),
+        };
+        match result {
+            Err(ApiError::RateLimited(_)) if retries < RATE_LIMIT_RETRIES => {
+                thread::sleep(backoff);
+                backoff *= 2;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// One request straight to the API, authenticated with `token`.
+fn http_request(
+    repo: &GitHubRepo,
+    token: &str,
+    path: &str,
+    graphql_body: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, ApiError> {
+    let url = api_url(repo, path);
+    let authorization = format!("Bearer {}", token);
+    let response = match graphql_body {
+        Some(body) => http_agent()
+            .post(&url)
+            .header("Authorization", &authorization)
+            .header("User-Agent", "blame")
+            .send(body.to_string()),
+        None => http_agent()
+            .get(&url)
+            .header("Authorization", &authorization)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "blame")
+            .call(),
+    };
+    let mut response = response.map_err(|_| ApiError::Failed)?;
+    let status = response.status().as_u16();
+    let header = |name: &str| -> Option<i64> {
+        response
+            .headers()
+            .get(name)?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    // A secondary limit says how long to wait, a primary one when it resets
+    let reset = match header("retry-after") {
+        Some(seconds) => Some(Utc::now() + chrono::Duration::seconds(seconds)),
+        None => header("x-ratelimit-reset").and_then(|t| Utc.timestamp_opt(t, 0).single()),
+    };
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|_| ApiError::Failed)?;
+
+    // GitHub answers 403 for primary and secondary rate limits, 429 for some
+    let rate_limited =
+        status == 429 || (status == 403 && body.to_lowercase().contains("rate limit"));
+    match status {
+        200..=299 => {
+            let response = serde_json::from_str(&body).map_err(|_| ApiError::Failed)?;
+            if is_graphql_rate_limited(&response) {
+                return Err(ApiError::RateLimited(reset));
+            }
+            Ok(response)
+        }
+        _ if rate_limited => Err(ApiError::RateLimited(reset)),
+        _ => Err(ApiError::Failed),
+    }
+}
+
+/// Whether a GraphQL response is GitHub refusing the query for the rate
+/// limit, which it does with a 200 and an error of type `RATE_LIMITED`.
+fn is_graphql_rate_limited(response: &serde_json::Value) -> bool {
+    response
+        .get("errors")
+        .and_then(|errors| errors.as_array())
+        .is_some_and(|errors| {
+            errors
+                .iter()
+                .any(|error| error.get("type").and_then(|t| t.as_str()) == Some("RATE_LIMITED"))
+        })
+}
+
+/// One request through `gh api`, which handles authentication itself.
+fn gh_request(
+    repo: &GitHubRepo,
+    path: &str,
+    graphql_body: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, ApiError> {
+    let mut command = gh_command();
+    command.arg("api").args(gh_host_args(repo)).arg(path);
+    if let Some(body) = graphql_body {
+        command.args([
+            "-f",
+            &format!("query={}", body["query"].as_str().unwrap_or("")),
+        ]);
+        if let Some(variables) = body["variables"].as_object() {
+            for (name, value) in variables {
+                command.args(["-f", &format!("{}={}", name, value.as_str().unwrap_or(""))]);
+            }
+        }
+    }
+    let output = command.output().map_err(|_| ApiError::Failed)?;
+    // gh prints the body of a GraphQL error response too, then fails
+    let response: Option<serde_json::Value> = serde_json::from_slice(&output.stdout).ok();
+    if response.as_ref().is_some_and(is_graphql_rate_limited) {
+        return Err(ApiError::RateLimited(None));
+    }
+
+    if !output.status.success() {
+        // e.g. "API rate limit exceeded" or "exceeded a secondary rate limit"
+        let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+        if stderr.contains("http 429") || stderr.contains("rate limit") {
+            return Err(ApiError::RateLimited(None));
+        }
+        return Err(ApiError::Failed);
+    }
+    response.ok_or(ApiError::Failed)
+}
+
+/// The login of the author of `sha`, or `None` if GitHub doesn't know one.
+fn get_github_username(repo: &GitHubRepo, sha: &str) -> Result<Option<String>, ApiError> {
+    let path = format!("repos/{}/{}/commits/{}", repo.owner, repo.repo, sha);
+    let commit = match api_request(repo, &path, None) {
+        Ok(commit) => commit,
+        Err(ApiError::Failed) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(commit
+        .pointer("/author/login")
+        .and_then(|login| login.as_str())
+        .filter(|login| !login.is_empty())
+        .map(String::from))
+}
+
+/// Extract the login from `12345+login@users.noreply.github.com` or the older
+/// `login@users.noreply.github.com` form.
+fn login_from_noreply_email(email: &str) -> Option<&str> {
+    let local = email.strip_suffix("@users.noreply.github.com")?;
+    let login = match local.split_once('+') {
+        Some((id, login)) if id.chars().all(|c| c.is_ascii_digit()) => login,
+        _ => local,
+    };
+    if login.is_empty() { None } else { Some(login) }
+}
+
+/// Directory for blame's on-disk caches (`$XDG_CACHE_HOME/blame`).
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(cache_dir.join("blame"))
+}
+
+fn login_cache_path() -> Option<PathBuf> {
+    Some(cache_dir()?.join("gh-logins.json"))
+}
+
+fn read_login_cache(path: &Path) -> HashMap<String, String> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Number of commits to look up per GraphQL request.
+const GRAPHQL_BATCH_SIZE: usize = 50;
+
+/// Resolve the GitHub logins for many commits at once via the GraphQL API.
+/// Fails if any request does, so callers can fall back to REST, unless GitHub
+/// rate-limited it: then REST would be refused too, and the logins resolved
+/// so far come back with the error.
+fn get_github_usernames_batch(
+    repo: &GitHubRepo,
+    shas: &[&str],
+) -> Result<HashMap<String, String>, (HashMap<String, String>, ApiError)> {
+    let mut logins: HashMap<String, String> = HashMap::new();
+
+    for chunk in shas.chunks(GRAPHQL_BATCH_SIZE) {
+        let fields: String = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, sha)| {
+                format!(
+                    "c{}: object(oid: \"{}\") {{ ... on Commit {{ author {{ user {{ login }} }} }} }} ",
+                    i, sha
+                )
+            })
+            .collect();
+        let query = format!(
+            "query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ {}}} }}",
+            fields
+        );
+
+        let body = serde_json::json!({
+            "query": query,
+            "variables": { "owner": repo.owner, "name": repo.repo },
+        });
+        let response = match api_request(repo, "graphql", Some(&body)) {
+            Ok(response) => response,
+            Err(e) => return Err((logins, e)),
+        };
+        let Some(repository) = response.pointer("/data/repository") else {
+            return Err((logins, ApiError::Failed));
+        };
+
+        for (i, sha) in chunk.iter().enumerate() {
+            let login = repository
+                .get(format!("c{}", i))
+                .and_then(|c| c.pointer("/author/user/login"))
+                .and_then(|l| l.as_str());
+            if let Some(login) = login {
+                logins.insert(sha.to_string(), login.to_string());
+            }
+        }
+    }
+
+    Ok(logins)
+}
+
+/// Resolve the GitHub logins for `shas` with one REST call each, running up to
+/// [`MAX_CONCURRENT_LOOKUPS`] at a time. Commits without a known login are
+/// left out. Fails, with the logins resolved so far, if GitHub rate-limited
+/// the lookups, in which case the rest are abandoned.
+fn get_github_usernames_rest(
+    repo: &GitHubRepo,
+    shas: &[&str],
+) -> Result<HashMap<String, String>, (HashMap<String, String>, ApiError)> {
+    let next_sha = AtomicUsize::new(0);
+    let rate_limited = AtomicBool::new(false);
+    let reset: Mutex<Option<DateTime<Utc>>> = Mutex::new(None);
+    let workers = MAX_CONCURRENT_LOOKUPS.min(shas.len());
+
+    let logins = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut logins = HashMap::new();
+                    while let Some(sha) = shas.get(next_sha.fetch_add(1, Ordering::Relaxed)) {
+                        if rate_limited.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        match get_github_username(repo, sha) {
+                            Ok(Some(login)) => {
+                                logins.insert(sha.to_string(), login);
+                            }
+                            Ok(None) => {}
+                            Err(ApiError::RateLimited(when)) => {
+                                rate_limited.store(true, Ordering::Relaxed);
+                                *reset.lock().unwrap() = when;
+                            }
+                            Err(ApiError::Failed) => {}
+                        }
+                    }
+                    logins
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    if rate_limited.into_inner() {
+        Err((logins, ApiError::RateLimited(reset.into_inner().unwrap())))
+    } else {
+        Ok(logins)
+    }
+}
+
+/// Re-key `stats` by GitHub login, merging authors that map to the same login.
+/// Authors whose login can't be found keep their git name. Also returns the
+/// set of keys that are real logins.
+///
+/// Fails with [`RateLimited`] if GitHub rate-limits the lookups; rename with
+/// its partial logins to carry on anyway.
+pub fn resolve_github_usernames(
+    stats: HashMap<String, AuthorStats>,
+    repo: &GitHubRepo,
+    cache: &mut LoginCache,
+) -> Result<(HashMap<String, AuthorStats>, HashSet<String>), RateLimited> {
+    let logins = resolve_github_logins(&stats, repo, cache)?;
+    let stats = rename_authors(stats, &logins);
+    Ok((stats, logins.into_values().collect()))
+}
+
+/// Map each author in `stats` to their GitHub login, leaving out authors whose
+/// login can't be found. Fails with [`RateLimited`], holding whatever logins
+/// were found, if GitHub rate-limits the lookups.
+pub fn resolve_github_logins(
+    stats: &HashMap<String, AuthorStats>,
+    repo: &GitHubRepo,
+    cache: &mut LoginCache,
+) -> Result<HashMap<String, String>, RateLimited> {
+    let mut rate_limited = None;
+    let mut author_to_gh: HashMap<String, Option<String>> = HashMap::new();
+
+    // Use one representative commit per author to find their GitHub username
+    let mut author_shas: Vec<(&String, &str)> = Vec::new();
+    for (author, author_stats) in stats {
+        // GitHub noreply addresses encode the login directly, no API call needed
+        let noreply_login = author_stats
+            .emails
+            .iter()
+            .filter_map(|email| login_from_noreply_email(email))
+            .min();
+        if let Some(login) = noreply_login {
+            author_to_gh.insert(author.clone(), Some(login.to_string()));
+            continue;
+        }
+
+        // Any of the author's commits may have been looked up before
+        if let Some(login) = author_stats
+            .commits
+            .iter()
+            .find_map(|sha| cache.get(repo, sha))
+        {
+            author_to_gh.insert(author.clone(), Some(login.clone()));
+            continue;
+        }
+        // Otherwise look up the same commit every run, so the cache hits next time
+        let Some(sha) = author_stats
+            .last_commit_sha()
+            .or_else(|| author_stats.commits.iter().min().map(String::as_str))
+        else {
+            continue;
+        };
+        author_shas.push((author, sha));
+    }
+
+    if !author_shas.is_empty() {
+        let shas: Vec<&str> = author_shas.iter().map(|(_, sha)| *sha).collect();
+        // Fall back to one REST call per commit if GraphQL failed for any
+        // reason but the rate limit, which REST shares
+        let result = match get_github_usernames_batch(repo, &shas) {
+            Err((_, ApiError::Failed)) => get_github_usernames_rest(repo, &shas),
+            result => result,
+        };
+        let logins = match result {
+            Ok(logins) => logins,
+            Err((logins, ApiError::RateLimited(reset))) => {
+                rate_limited = Some(reset);
+                logins
+            }
+            Err((logins, ApiError::Failed)) => logins,
+        };
+
+        for (author, sha) in author_shas {
+            let gh_user = logins.get(sha).cloned();
+            if let Some(login) = &gh_user {
+                cache.insert(repo, sha, login.clone());
+            }
+            author_to_gh.insert(author.clone(), gh_user);
+        }
+    }
+
+    let logins = author_to_gh
+        .into_iter()
+        .filter_map(|(author, login)| Some((author, login?)))
+        .collect();
+    match rate_limited {
+        Some(reset) => Err(RateLimited { logins, reset }),
+        None => Ok(logins),
+    }
+}
+
+/// Longest to wait for each way of finding the latest release, so checking
+/// for updates can't hang a run on a slow network.
+const RELEASE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tag of the latest published release of blame itself, via `gh` if it's
+/// installed and otherwise the public REST API.
+pub fn get_latest_release_tag() -> Option<String> {
+    const RELEASES_PATH: &str = "repos/flaque/blame/releases/latest";
+
+    let mut command = gh_command();
+    command.args(["api", RELEASES_PATH, "--jq", ".tag_name"]);
+    if let Ok(output) = output_with_timeout(&mut command, Some(RELEASE_CHECK_TIMEOUT))
+        && output.status.success()
+    {
+        let tag = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return Some(tag).filter(|t| !t.is_empty());
+    }
+
+    let mut response = http_agent()
+        .get(format!("https://api.github.com/{}", RELEASES_PATH))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "blame")
+        .config()
+        .timeout_global(Some(RELEASE_CHECK_TIMEOUT))
+        .build()
+        .call()
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let body = response.body_mut().read_to_string().ok()?;
+    let release: serde_json::Value = serde_json::from_str(&body).ok()?;
+    release["tag_name"].as_str().map(String::from)
+}
+
+/// The login the API token, or else `gh`, is authenticated as.
+pub fn get_current_github_user(repo: &GitHubRepo) -> Option<String> {
+    let user = api_request(repo, "user", None).ok()?;
+    user["login"]
+        .as_str()
+        .filter(|login| !login.is_empty())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn graphql_rate_limit_errors_are_recognized() {
+        let limited = json!({
+            "data": null,
+            "errors": [{ "type": "RATE_LIMITED", "message": "API rate limit exceeded" }],
+        });
+        assert!(is_graphql_rate_limited(&limited));
+    }
+
+    #[test]
+    fn other_graphql_errors_are_not_rate_limits() {
+        let not_found = json!({
+            "data": { "repository": null },
+            "errors": [{ "type": "NOT_FOUND", "message": "Could not resolve" }],
+        });
+        assert!(!is_graphql_rate_limited(&not_found));
+        assert!(!is_graphql_rate_limited(
+            &json!({ "data": { "repository": {} } })
+        ));
+    }
+
+    #[test]
+    fn rate_limit_message_says_when_to_retry() {
+        let unknown = RateLimited {
+            logins: HashMap::new(),
+            reset: None,
+        };
+        assert!(unknown.to_string().contains("try again later"));
+
+        let reset = Utc.timestamp_opt(1_700_000_000, 0).single();
+        let known = RateLimited {
+            logins: HashMap::new(),
+            reset,
+        };
+        let local = reset.unwrap().with_timezone(&Local).format("%H:%M");
+        assert!(
+            known
+                .to_string()
+                .contains(&format!("try again after {}", local))
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! `.blameignore`: gitignore-style patterns, checked into a repository, for
+//! files whose lines say nothing about ownership (generated code, vendored
+//! dependencies, lockfiles).
+
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+
+/// Name of the ignore file, read from the repository root.
+pub const BLAMEIGNORE_FILE: &str = ".blameignore";
+
+/// `*` and `?` stop at `/`, as in `.gitignore`.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// One line of a `.blameignore`.
+struct Rule {
+    pattern: Pattern,
+    /// `!pattern`: un-ignore what an earlier rule ignored.
+    negated: bool,
+    /// `pattern/`: only match directories.
+    dir_only: bool,
+    /// Contains a `/` other than a trailing one, so it's matched against the
+    /// whole path from the root instead of any single name.
+    anchored: bool,
+}
+
+/// The rules of a `.blameignore`. As in `.gitignore`, the last rule matching a
+/// path (or one of its directories) decides whether it is ignored.
+pub struct BlameIgnore {
+    rules: Vec<Rule>,
+}
+
+impl BlameIgnore {
+    /// Parse the contents of a `.blameignore`, returning the rules along with
+    /// any lines that aren't valid patterns.
+    pub fn parse(contents: &str) -> (BlameIgnore, Vec<String>) {
+        let mut rules = Vec::new();
+        let mut invalid = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, rest) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, rest) = match rest.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let anchored = rest.contains('/');
+            let rest = rest.strip_prefix('/').unwrap_or(rest);
+
+            match Pattern::new(rest) {
+                Ok(pattern) if !rest.is_empty() => rules.push(Rule {
+                    pattern,
+                    negated,
+                    dir_only,
+                    anchored,
+                }),
+                _ => invalid.push(line.to_string()),
+            }
+        }
+        (BlameIgnore { rules }, invalid)
+    }
+
+    /// Whether `path`, relative to the repository root, is ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let components: Vec<_> = path.components().collect();
+        let mut ignored = false;
+        for rule in &self.rules {
+            // A rule covers a file when it matches the file itself or any of
+            // the directories it's in
+            let matched = (1..=components.len()).any(|depth| {
+                let is_file = depth == components.len();
+                if is_file && rule.dir_only {
+                    return false;
+                }
+                if rule.anchored {
+                    let prefix: PathBuf = components[..depth].iter().collect();
+                    rule.pattern.matches_path_with(&prefix, MATCH_OPTIONS)
+                } else {
+                    let name = components[depth - 1].as_os_str().to_string_lossy();
+                    rule.pattern.matches_with(&name, MATCH_OPTIONS)
+                }
+            });
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
@@ -0,0 +1,1859 @@
+//! Find out who is responsible for a file or folder using git (or Mercurial)
+//! blame.
+//!
+//! The `blame` binary is a thin wrapper around [`blame_paths`], which expands
+//! patterns into tracked files and tallies per-author stats for them.
+
+mod comments;
+pub mod github;
+mod ignore;
+pub mod vcs;
+
+use chrono::{Datelike, TimeZone, Utc};
+use glob::{Pattern, glob};
+use ignore::{BLAMEIGNORE_FILE, BlameIgnore};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+use vcs::{BlameHunk, CoAuthor, GitBackend, HgBackend, VcsBackend, detect_vcs, repository_root};
+
+/// Program [`git_command`] runs, once chosen by [`set_git_program`].
+static GIT_PROGRAM: OnceLock<OsString> = OnceLock::new();
+
+/// Run git commands with `program` instead of the first `git` on `PATH`
+/// (or `$BLAME_GIT`). Only the first call has any effect.
+pub fn set_git_program(program: impl Into<OsString>) {
+    let _ = GIT_PROGRAM.set(program.into());
+}
+
+/// A `git` command, using the program from [`set_git_program`] or `$BLAME_GIT`
+/// when set.
+pub fn git_command() -> Command {
+    let program = GIT_PROGRAM.get_or_init(|| {
+        std::env::var_os("BLAME_GIT")
+            .filter(|p| !p.is_empty())
+            .unwrap_or_else(|| "git".into())
+    });
+    Command::new(program)
+}
+
+/// Per-author totals collected from blame output.
+#[derive(Clone, Default)]
+pub struct AuthorStats {
+    lines: usize,
+    weighted_lines: f64,
+    last_commit_time: i64,
+    /// Time of the author's oldest blamed line; `None` until a hunk is added.
+    first_commit_time: Option<i64>,
+    /// SHA of the newest commit behind the author's lines, with its time.
+    /// Kept apart from `last_commit_time`, which also covers uncommitted lines.
+    last_commit_sha: Option<(i64, String)>,
+    commits: HashSet<String>,
+    /// Time of each of `commits`.
+    commit_times: HashMap<String, i64>,
+    emails: HashSet<String>,
+    /// Files the author has at least one blamed line in.
+    files: HashSet<String>,
+    /// Lines under each name the author's hunks were recorded with.
+    names: HashMap<String, usize>,
+    /// Lines last touched in each month, keyed by [`month_of`].
+    monthly_lines: BTreeMap<(i32, u32), usize>,
+}
+
+impl AuthorStats {
+    /// Number of lines attributed to this author.
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Lines weighted by recency when [`BlameOptions::decay`] is set, and
+    /// split between a commit's authors with [`BlameOptions::co_authors`];
+    /// equal to [`lines`](Self::lines) otherwise.
+    pub fn weighted_lines(&self) -> f64 {
+        self.weighted_lines
+    }
+
+    /// Unix timestamp of the author's most recent blamed line.
+    pub fn last_commit_time(&self) -> i64 {
+        self.last_commit_time
+    }
+
+    /// Unix timestamp of the author's oldest blamed line, i.e. how long they
+    /// have owned what they own.
+    pub fn first_commit_time(&self) -> i64 {
+        self.first_commit_time.unwrap_or(0)
+    }
+
+    /// SHA of the author's most recent commit among their blamed lines, if any
+    /// are committed.
+    pub fn last_commit_sha(&self) -> Option<&str> {
+        self.last_commit_sha.as_ref().map(|(_, sha)| sha.as_str())
+    }
+
+    /// Unique commits that contributed the author's lines.
+    pub fn commits(&self) -> &HashSet<String> {
+        &self.commits
+    }
+
+    /// Time of each of the author's [`commits`](Self::commits), by SHA.
+    pub fn commit_times(&self) -> &HashMap<String, i64> {
+        &self.commit_times
+    }
+
+    /// Email addresses the author committed under.
+    pub fn emails(&self) -> &HashSet<String> {
+        &self.emails
+    }
+
+    /// Files the author has at least one blamed line in: how widely spread
+    /// their ownership is.
+    pub fn files(&self) -> &HashSet<String> {
+        &self.files
+    }
+
+    /// How many of the author's lines were last touched in each (year,
+    /// month), in UTC. Months without any are left out.
+    pub fn monthly_lines(&self) -> &BTreeMap<(i32, u32), usize> {
+        &self.monthly_lines
+    }
+
+    fn add_hunk(&mut self, file: &str, hunk: BlameHunk, weight: f64) {
+        if hunk.lines > 0 && !self.files.contains(file) {
+            self.files.insert(file.to_string());
+        }
+        self.lines += hunk.lines;
+        self.weighted_lines += hunk.lines as f64 * weight;
+        if hunk.time > self.last_commit_time {
+            self.last_commit_time = hunk.time;
+        }
+        self.note_first_time(hunk.time);
+        if !hunk.is_uncommitted() {
+            self.note_commit(hunk.time, &hunk.sha);
+            self.commit_times.insert(hunk.sha.clone(), hunk.time);
+            self.commits.insert(hunk.sha);
+        }
+        if let Some(mail) = hunk.mail {
+            self.emails.insert(mail);
+        }
+        *self.names.entry(hunk.author).or_default() += hunk.lines;
+        if let Some(month) = month_of(hunk.time).filter(|_| hunk.lines > 0) {
+            *self.monthly_lines.entry(month).or_default() += hunk.lines;
+        }
+    }
+
+    /// Fold another set of stats for the same author into this one.
+    pub fn merge(&mut self, other: AuthorStats) {
+        self.lines += other.lines;
+        self.weighted_lines += other.weighted_lines;
+        if other.last_commit_time > self.last_commit_time {
+            self.last_commit_time = other.last_commit_time;
+        }
+        if let Some(time) = other.first_commit_time {
+            self.note_first_time(time);
+        }
+        if let Some((time, sha)) = &other.last_commit_sha {
+            self.note_commit(*time, sha);
+        }
+        self.commits.extend(other.commits);
+        self.commit_times.extend(other.commit_times);
+        self.emails.extend(other.emails);
+        self.files.extend(other.files);
+        for (name, lines) in other.names {
+            *self.names.entry(name).or_default() += lines;
+        }
+        for (month, lines) in other.monthly_lines {
+            *self.monthly_lines.entry(month).or_default() += lines;
+        }
+    }
+
+    fn scale_weight(&mut self, factor: f64) {
+        self.weighted_lines *= factor;
+    }
+
+    /// The name most of the author's lines were recorded under; ties go to
+    /// the alphabetically first.
+    fn most_common_name(&self) -> Option<&str> {
+        self.names
+            .iter()
+            .max_by(|(a_name, a), (b_name, b)| a.cmp(b).then(b_name.cmp(a_name)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn note_first_time(&mut self, time: i64) {
+        self.first_commit_time = Some(self.first_commit_time.map_or(time, |first| first.min(time)));
+    }
+
+    /// Keep `sha` as the last commit if it is newer. Ties go to the larger SHA
+    /// so the result doesn't depend on the order hunks arrive in.
+    fn note_commit(&mut self, time: i64, sha: &str) {
+        let newer = match &self.last_commit_sha {
+            Some((last_time, last_sha)) => (time, sha) > (*last_time, last_sha.as_str()),
+            None => true,
+        };
+        if newer {
+            self.last_commit_sha = Some((time, sha.to_string()));
+        }
+    }
+}
+
+/// The (year, month) a Unix timestamp falls in, in UTC.
+pub fn month_of(time: i64) -> Option<(i32, u32)> {
+    let date = Utc.timestamp_opt(time, 0).single()?;
+    Some((date.year(), date.month()))
+}
+
+/// How git blame data is obtained.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Run `git blame --line-porcelain` for each file.
+    #[default]
+    Subprocess,
+    /// Blame in-process with libgit2. Doesn't support `since`, `function`, or
+    /// ignore-revs files.
+    #[cfg(feature = "git2")]
+    Git2,
+}
+
+/// Options controlling which files are blamed and how.
+#[derive(Clone, Default)]
+pub struct BlameOptions {
+    /// Only attribute lines touched since this date.
+    pub since: Option<String>,
+    /// Blame the tree as of the last commit before this date.
+    pub until: Option<String>,
+    /// Ignore whitespace-only changes (`git blame -w`).
+    pub ignore_whitespace: bool,
+    /// Don't credit anyone for empty or whitespace-only lines, so spacing
+    /// doesn't count as ownership. Shifts every share when set.
+    pub ignore_blank_lines: bool,
+    /// Don't credit anyone for lines that are only a comment, in languages
+    /// recognized by file extension, so shares reflect code. Comments are
+    /// spotted heuristically: see the `comments` module.
+    pub ignore_comments: bool,
+    /// Follow lines across renames and copies (`-C -C -M`).
+    pub follow: bool,
+    /// Follow only the first parent of merges (`git blame --first-parent`),
+    /// so lines that came in with a merge are credited to the merge commit
+    /// rather than the branch commits behind it: ownership as seen on the
+    /// mainline.
+    pub first_parent: bool,
+    /// Revisions to ignore; defaults to `.git-blame-ignore-revs` when present.
+    pub ignore_revs_file: Option<PathBuf>,
+    /// Blame at this revision instead of the working tree.
+    pub rev: Option<String>,
+    /// Only blame this function (requires a single file).
+    pub function: Option<String>,
+    /// Number of files to blame in parallel; defaults to the number of CPUs.
+    pub jobs: Option<usize>,
+    /// Which blame implementation to use.
+    pub backend: Backend,
+    /// Half-life for recency weighting: a line this old counts half as much
+    /// toward [`AuthorStats::weighted_lines`] as one written now.
+    pub decay: Option<Duration>,
+    /// Also keep stats for each file in [`BlameReport::file_stats`].
+    pub per_file: bool,
+    /// Count every file equally: [`AuthorStats::weighted_lines`] in
+    /// [`BlameReport::stats`] become the sum of the author's shares of each
+    /// file (so they add up to the number of files), and a 10,000-line
+    /// generated file weighs no more than a short one.
+    pub per_file_weight: bool,
+    /// Only blame files at most this many levels below a directory argument;
+    /// 1 means its direct children.
+    pub max_depth: Option<usize>,
+    /// Leave merge commits out of [`AuthorStats::commits`]. Lines git blames on
+    /// a merge (such as conflict resolutions) still count toward its author.
+    pub no_merges: bool,
+    /// Glob patterns for files to leave out, like a `!`-prefixed pattern.
+    pub exclude: Vec<String>,
+    /// Don't leave out the files listed in the repository's `.blameignore`.
+    pub no_ignore: bool,
+    /// Blame files that look binary (a NUL byte near the start, as git
+    /// judges it) instead of skipping them with a warning. Their "lines" are
+    /// rarely meaningful.
+    pub include_binary: bool,
+    /// Skip files larger than this many bytes, with a warning, rather than
+    /// spend most of a run blaming a huge generated or data file.
+    pub max_file_size: Option<u64>,
+    /// Count lines that aren't committed yet under [`UNCOMMITTED_AUTHOR`]
+    /// instead of skipping them.
+    pub include_uncommitted: bool,
+    /// Don't print warnings about unmatched patterns, untracked paths, or
+    /// files that couldn't be blamed. Errors are still returned.
+    pub quiet: bool,
+    /// Count files named explicitly that exist but aren't tracked, with every
+    /// line under [`UNCOMMITTED_AUTHOR`]. Ignored with [`BlameOptions::rev`] or
+    /// [`BlameOptions::until`].
+    pub include_untracked: bool,
+    /// Key stats by (lowercased) author email instead of name, so one person
+    /// committing under several names is counted once. Each is reported under
+    /// the name most of their lines carry.
+    pub merge_by_email: bool,
+    /// Merge authors whose names differ only in case or whitespace ("John Doe"
+    /// and "john  doe "), reporting them under the spelling with the most
+    /// lines.
+    pub normalize_names: bool,
+    /// Fetch the rest of the history first when the repository is a shallow
+    /// clone, instead of warning that its results are unreliable.
+    pub unshallow: bool,
+    /// Credit lines to whoever committed them rather than who wrote them, so
+    /// a maintainer who rebased or applied a patch gets the lines. Every
+    /// "author" in the results is then a committer.
+    pub committer: bool,
+    /// Share each line equally between its commit's author and the people
+    /// named in `Co-authored-by:` trailers. The split shows up in
+    /// [`AuthorStats::weighted_lines`]; [`AuthorStats::lines`] counts every
+    /// line a person shares in full.
+    pub co_authors: bool,
+    /// Show a progress bar on stderr while blaming, when it is a terminal and
+    /// there are enough files for it to be worth it.
+    pub progress: bool,
+    /// Kill a file's blame subprocess if it runs longer than this, skipping
+    /// the file with a warning. The git2 backend blames in-process and isn't
+    /// limited.
+    pub timeout: Option<Duration>,
+    /// Stop once the files to blame are known: [`BlameReport::files`] and
+    /// [`BlameReport::skipped`] are filled in, but nothing is blamed.
+    pub dry_run: bool,
+    /// Lines of particular files to blame besides those the patterns match, as
+    /// (path, first line, last line). Paths are taken literally, with no glob
+    /// or `~` expansion, and need only be tracked at [`BlameOptions::rev`], so
+    /// a file since deleted or renamed is still blamed.
+    pub line_ranges: Vec<(PathBuf, usize, usize)>,
+}
+
+/// Author that uncommitted lines are counted under with
+/// [`BlameOptions::include_uncommitted`].
+pub const UNCOMMITTED_AUTHOR: &str = "(uncommitted)";
+
+/// Errors returned by [`blame_paths`] and [`collect_blame_stats`].
+#[derive(Debug, Error)]
+pub enum BlameError {
+    /// The `git` binary could not be run.
+    #[error("git is not installed or not on PATH")]
+    GitNotFound,
+    /// The `hg` binary could not be run.
+    #[error("hg is not installed or not on PATH")]
+    HgNotFound,
+    /// The path is not inside a git or Mercurial repository.
+    #[error("'{}' is not in a git or Mercurial repository", .0.display())]
+    NotARepository(PathBuf),
+    /// `--rev` does not name a commit.
+    #[error("Unknown revision '{0}'")]
+    UnknownRevision(String),
+    /// `--until` is older than every commit.
+    #[error("No commits found before '{0}'")]
+    NoCommitsBefore(String),
+    /// The ignore-revs file does not exist.
+    #[error("Ignore-revs file '{}' not found", .0.display())]
+    IgnoreRevsFileNotFound(PathBuf),
+    /// An exclusion pattern isn't a valid glob.
+    #[error("Invalid exclude pattern '{0}'")]
+    InvalidExcludePattern(String),
+    /// `--function` was combined with more than one file.
+    #[error("--function can only be used with a single file")]
+    FunctionNeedsSingleFile,
+    /// The blame command exited unsuccessfully.
+    #[error("{stderr}")]
+    BlameFailed { stderr: String },
+    /// The file was deleted before it could be blamed, and isn't in `HEAD`
+    /// either.
+    #[error("file no longer present")]
+    FileRemoved,
+    /// A blame subprocess ran longer than [`BlameOptions::timeout`] and was
+    /// killed.
+    #[error("timed out after {}s", .0.as_secs_f64())]
+    TimedOut(Duration),
+    /// Fetching the rest of a shallow clone's history failed.
+    #[error("could not fetch full history: {stderr}")]
+    UnshallowFailed { stderr: String },
+    /// The repository's VCS or the selected [`Backend`] can't honor one of the
+    /// options.
+    #[error("{option} is not supported by the {backend} backend")]
+    UnsupportedByBackend {
+        option: &'static str,
+        backend: &'static str,
+    },
+    /// Blame produced output we couldn't understand.
+    #[error("Could not parse blame output: {0}")]
+    ParseError(String),
+    /// Some other I/O failure while running the VCS.
+    #[error("Failed to run blame: {0}")]
+    Io(io::Error),
+}
+
+impl From<io::Error> for BlameError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::NotFound {
+            BlameError::GitNotFound
+        } else {
+            BlameError::Io(e)
+        }
+    }
+}
+
+/// The result of [`blame_paths`].
+pub struct BlameReport {
+    /// Root of the repository the files live in, if any path matched.
+    pub root: Option<PathBuf>,
+    /// Name of the repository's VCS (`"git"` or `"hg"`), if any path matched.
+    pub vcs: Option<&'static str>,
+    /// Every file that was blamed.
+    pub files: Vec<String>,
+    /// Stats keyed by (mailmap-canonicalized) author name. Empty when the
+    /// files have no blamable lines, e.g. they are empty or nothing has been
+    /// committed yet.
+    pub stats: HashMap<String, AuthorStats>,
+    /// Stats for each file, keyed like `stats`; only filled in when
+    /// [`BlameOptions::per_file`] is set.
+    pub file_stats: HashMap<String, HashMap<String, AuthorStats>>,
+    /// Files that could not be blamed; a warning was printed for each.
+    pub failed: Vec<String>,
+    /// Paths that patterns matched but that weren't blamed, and why, sorted
+    /// by path. Unmatched patterns are listed under the pattern itself.
+    pub skipped: Vec<(String, SkipReason)>,
+}
+
+/// Why [`blame_paths`] left a path out, in [`BlameReport::skipped`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The pattern matched nothing.
+    Unmatched,
+    /// The path isn't tracked (at [`BlameOptions::rev`], if set).
+    Untracked,
+    /// The path isn't in the repository the first matched path is in.
+    OutsideRepository,
+    /// The file is more than [`BlameOptions::max_depth`] levels below the
+    /// directory that matched it.
+    TooDeep,
+    /// The file matched this `!`-prefixed or [`BlameOptions::exclude`]
+    /// pattern.
+    Excluded(String),
+    /// The file is listed in the repository's `.blameignore`.
+    Ignored,
+    /// The file looks binary; see [`BlameOptions::include_binary`].
+    Binary,
+    /// The file is larger than [`BlameOptions::max_file_size`].
+    TooLarge,
+    /// The path is in (or is) a submodule that isn't checked out, or that
+    /// can't be blamed at [`BlameOptions::rev`] or [`BlameOptions::until`].
+    Submodule,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unmatched => write!(f, "no files matched"),
+            SkipReason::Untracked => write!(f, "not tracked"),
+            SkipReason::OutsideRepository => write!(f, "outside the repository"),
+            SkipReason::TooDeep => write!(f, "deeper than --max-depth"),
+            SkipReason::Excluded(pattern) => write!(f, "excluded by '{}'", pattern),
+            SkipReason::Ignored => write!(f, "listed in {}", BLAMEIGNORE_FILE),
+            SkipReason::Binary => write!(f, "binary"),
+            SkipReason::TooLarge => write!(f, "larger than --max-file-size"),
+            SkipReason::Submodule => write!(f, "in a submodule that can't be blamed"),
+        }
+    }
+}
+
+/// Expand `patterns` into tracked files and blame all of them. The VCS is
+/// detected from the first path that matches. Patterns starting with `!`, like
+/// [`BlameOptions::exclude`], remove files matched by any other pattern.
+///
+/// Finding no files is not an error: the report just comes back empty.
+pub fn blame_paths(patterns: &[String], options: &BlameOptions) -> Result<BlameReport, BlameError> {
+    // Expand all patterns and collect unique files, along with any `-L` line
+    // ranges to restrict them to (no ranges means the whole file)
+    let mut all_files: HashMap<String, Vec<String>> = HashMap::new();
+    let mut untracked: HashSet<String> = HashSet::new();
+    let mut skipped: Vec<(String, SkipReason)> = Vec::new();
+    let mut repo: Option<(Box<dyn VcsBackend>, PathBuf)> = None;
+    // Submodules of the repository, and the root of each file in one
+    let mut submodules: Vec<PathBuf> = Vec::new();
+    let mut file_roots: HashMap<String, PathBuf> = HashMap::new();
+    // A superproject's revision means nothing to its submodules
+    let pinned = options.rev.is_some() || options.until.is_some();
+    let skip_submodule = |path: &Path, quietly: bool| {
+        if quietly {
+            return;
+        }
+        let why = if pinned {
+            "submodules can't be blamed with --rev or --until"
+        } else {
+            "it isn't checked out (try `git submodule update --init`)"
+        };
+        warn(
+            options,
+            format_args!("Skipping submodule '{}': {}", path.display(), why),
+        );
+    };
+
+    let (negated, patterns): (Vec<&String>, Vec<&String>) =
+        patterns.iter().partition(|p| p.starts_with('!'));
+    let exclusions = negated
+        .into_iter()
+        .map(|p| &p[1..])
+        .chain(options.exclude.iter().map(String::as_str))
+        .map(Exclusion::new)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for pattern in patterns {
+        let (mut expanded, line_range) = expand_pattern(pattern);
+        // A file deleted from the working tree is still blamed as last
+        // committed, so long as it's tracked
+        if expanded.is_empty()
+            && !is_glob(pattern)
+            && let Some(path) = missing_file_path(pattern)
+            && let Some(vcs) = detect_vcs(&path)
+            && let Some(root) = repository_root(vcs.as_ref(), &path)
+            && vcs.is_tracked(&path, &root, options.rev.as_deref())
+        {
+            expanded.push(path);
+        }
+
+        if expanded.is_empty() {
+            warn(options, format_args!("No files matched '{}'", pattern));
+            skipped.push((pattern.clone(), SkipReason::Unmatched));
+            continue;
+        }
+
+        for path in expanded {
+            // Detect the VCS and its root from the first valid path
+            if repo.is_none() {
+                let (vcs, root) = open_repository(&path, options)?;
+                submodules = vcs.submodules(&root, options.rev.as_deref());
+                repo = Some((vcs, root));
+            }
+            let (vcs, root) = repo.as_ref().expect("repository detected above");
+
+            // Only one repository is blamed per run: the first path's
+            if !path.starts_with(root) {
+                if !is_glob(pattern) {
+                    warn(
+                        options,
+                        format_args!(
+                            "Skipping '{}': it's outside the repository at {}, and only one \
+                             repository is blamed per run",
+                            path.display(),
+                            root.display()
+                        ),
+                    );
+                }
+                skipped.push((
+                    path.to_string_lossy().to_string(),
+                    SkipReason::OutsideRepository,
+                ));
+                continue;
+            }
+
+            if path.is_dir() {
+                if line_range.is_some() {
+                    warn(
+                        options,
+                        format_args!("Ignoring line range for directory '{}'", path.display()),
+                    );
+                }
+                for f in vcs.tracked_files(&path, root, options.rev.as_deref()) {
+                    let file = Path::new(&f);
+                    if !within_depth(file, &path, options.max_depth) {
+                        skipped.push((f, SkipReason::TooDeep));
+                        continue;
+                    }
+                    if submodules.iter().any(|s| s == file) {
+                        skip_submodule(file, false);
+                        skipped.push((f, SkipReason::Submodule));
+                        continue;
+                    }
+                    if submodules.iter().any(|s| file.starts_with(s))
+                        && let Some(file_root) = repository_root(vcs.as_ref(), file)
+                    {
+                        file_roots.insert(f.clone(), file_root);
+                    }
+                    all_files.insert(f, vec![]);
+                }
+                continue;
+            }
+
+            let in_submodule = submodules.iter().any(|s| path.starts_with(s));
+            if in_submodule && pinned {
+                skip_submodule(&path, is_glob(pattern));
+                skipped.push((path.to_string_lossy().to_string(), SkipReason::Submodule));
+                continue;
+            }
+            let file_root = if in_submodule {
+                repository_root(vcs.as_ref(), &path).unwrap_or_else(|| root.clone())
+            } else {
+                root.clone()
+            };
+
+            let tracked = vcs.is_tracked(&path, &file_root, options.rev.as_deref());
+            let include = options.include_untracked
+                && options.rev.is_none()
+                && options.until.is_none()
+                && !is_glob(pattern);
+            if tracked || include {
+                if !tracked {
+                    untracked.insert(path.to_string_lossy().to_string());
+                } else if file_root != *root {
+                    file_roots.insert(path.to_string_lossy().to_string(), file_root);
+                }
+                let ranges = all_files
+                    .entry(path.to_string_lossy().to_string())
+                    .or_insert_with(|| line_range.iter().cloned().collect());
+                // An unranged mention of a file means the whole file wins
+                match &line_range {
+                    Some(range) if !ranges.is_empty() && !ranges.contains(range) => {
+                        ranges.push(range.clone())
+                    }
+                    Some(_) => {}
+                    None => ranges.clear(),
+                }
+            } else {
+                skipped.push((path.to_string_lossy().to_string(), SkipReason::Untracked));
+                if is_glob(pattern) {
+                    continue;
+                }
+                match &options.rev {
+                    Some(rev) => warn(
+                        options,
+                        format_args!("'{}' is not tracked at {}", path.display(), rev),
+                    ),
+                    None => warn(
+                        options,
+                        format_args!(
+                            "'{}' exists but is not tracked by {}",
+                            path.display(),
+                            vcs.name()
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    for (path, start, end) in &options.line_ranges {
+        if repo.is_none() {
+            // The file may be gone from the working tree, and its directory too
+            let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+            let (vcs, root) = open_repository(existing, options)?;
+            submodules = vcs.submodules(&root, options.rev.as_deref());
+            repo = Some((vcs, root));
+        }
+        let (vcs, root) = repo.as_ref().expect("repository detected above");
+        let file = path.to_string_lossy().to_string();
+
+        if !path.starts_with(root) {
+            warn(
+                options,
+                format_args!(
+                    "Skipping '{}': it's outside the repository at {}",
+                    path.display(),
+                    root.display()
+                ),
+            );
+            skipped.push((file, SkipReason::OutsideRepository));
+            continue;
+        }
+        if submodules.iter().any(|s| path.starts_with(s)) {
+            skip_submodule(path, false);
+            skipped.push((file, SkipReason::Submodule));
+            continue;
+        }
+        if !vcs.is_tracked(path, root, options.rev.as_deref()) {
+            warn(
+                options,
+                format_args!(
+                    "'{}' is not tracked at {}",
+                    path.display(),
+                    options.rev.as_deref().unwrap_or("HEAD")
+                ),
+            );
+            skipped.push((file, SkipReason::Untracked));
+            continue;
+        }
+
+        let range = format!("{},{}", start, end);
+        let ranges = all_files.entry(file).or_insert_with(|| vec![range.clone()]);
+        if !ranges.is_empty() && !ranges.contains(&range) {
+            ranges.push(range);
+        }
+    }
+
+    // A file another pattern did pick up wasn't skipped after all
+    skipped.retain(|(path, _)| !all_files.contains_key(path));
+
+    // Exclusions apply after every positive pattern, so order doesn't matter
+    all_files.retain(
+        |file, _| match exclusions.iter().find(|e| e.matches(Path::new(file))) {
+            Some(exclusion) => {
+                skipped.push((file.clone(), SkipReason::Excluded(exclusion.source.clone())));
+                false
+            }
+            None => true,
+        },
+    );
+    if let Some((_, root)) = repo.as_ref().filter(|_| !options.no_ignore) {
+        let blameignore = load_blameignore(root, options);
+        all_files.retain(|file, _| {
+            let path = Path::new(file);
+            let ignored = blameignore.is_ignored(path.strip_prefix(root).unwrap_or(path));
+            if ignored {
+                skipped.push((file.clone(), SkipReason::Ignored));
+            }
+            !ignored
+        });
+    }
+    if let Some(max_size) = options.max_file_size {
+        all_files.retain(|file, _| {
+            let size = std::fs::metadata(file).map_or(0, |m| m.len());
+            if size <= max_size {
+                return true;
+            }
+            warn(
+                options,
+                format_args!(
+                    "Skipping '{}' ({} bytes), larger than --max-file-size",
+                    file, size
+                ),
+            );
+            skipped.push((file.clone(), SkipReason::TooLarge));
+            false
+        });
+    }
+    if !options.include_binary {
+        let before = all_files.len();
+        all_files.retain(|file, _| {
+            let binary = is_binary(Path::new(file));
+            if binary {
+                skipped.push((file.clone(), SkipReason::Binary));
+            }
+            !binary
+        });
+        let binaries = before - all_files.len();
+        if binaries > 0 {
+            warn(
+                options,
+                format_args!(
+                    "Skipping {} binary {}; pass --no-skip-binary to blame binary files too",
+                    binaries,
+                    if binaries == 1 { "file" } else { "files" }
+                ),
+            );
+        }
+    }
+    skipped.sort_by(|(a, _), (b, _)| a.cmp(b));
+    skipped.dedup_by(|(a, _), (b, _)| a == b);
+
+    let mut files: Vec<(String, Vec<String>)> = all_files.into_iter().collect();
+
+    if let Some(function) = &options.function {
+        if files.len() > 1 {
+            return Err(BlameError::FunctionNeedsSingleFile);
+        }
+        if let Some((_, ranges)) = files.first_mut() {
+            ranges.push(format!(":{}", function));
+        }
+    }
+
+    let Some((vcs, root)) = repo else {
+        return Ok(BlameReport {
+            root: None,
+            vcs: None,
+            files: vec![],
+            stats: HashMap::new(),
+            file_stats: HashMap::new(),
+            failed: vec![],
+            skipped,
+        });
+    };
+
+    if files.is_empty() || options.dry_run {
+        return Ok(BlameReport {
+            root: Some(root),
+            vcs: Some(vcs.name()),
+            files: files.into_iter().map(|(file, _)| file).collect(),
+            stats: HashMap::new(),
+            file_stats: HashMap::new(),
+            failed: vec![],
+            skipped,
+        });
+    }
+
+    // In a shallow clone every line older than the cut-off is blamed on the
+    // oldest fetched commit
+    if vcs.is_shallow(&root) {
+        if options.unshallow {
+            vcs.unshallow(&root)?;
+        } else {
+            warn(
+                options,
+                format_args!(
+                    "This is a shallow clone, so lines older than its history are blamed \
+                     on the oldest fetched commit and ownership may be wrong. Pass \
+                     --unshallow or run `git fetch --unshallow` for accurate results"
+                ),
+            );
+        }
+    }
+
+    // Blaming needs a commit to blame against; a fresh repository just has
+    // nothing to report yet, apart from untracked files
+    let has_history = options.rev.is_some() || options.until.is_some() || vcs.has_commits(&root);
+    let (untracked_files, tracked_files): (Vec<_>, Vec<_>) = files
+        .iter()
+        .cloned()
+        .partition(|(file, _)| untracked.contains(file));
+
+    let mut options = options.clone();
+    let explicit_ignore_revs = options.ignore_revs_file.is_some();
+
+    match &options.ignore_revs_file {
+        Some(path) => match path.canonicalize() {
+            Ok(path) => options.ignore_revs_file = Some(path),
+            Err(_) => return Err(BlameError::IgnoreRevsFileNotFound(path.clone())),
+        },
+        None => {
+            if let Some(default_path) = vcs.default_ignore_revs_file(&root) {
+                if options.backend == Backend::Subprocess {
+                    options.ignore_revs_file = Some(default_path);
+                } else {
+                    warn(
+                        &options,
+                        format_args!("Ignoring .git-blame-ignore-revs with the git2 backend"),
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(until) = &options.until {
+        match vcs.last_commit_before(until, options.rev.as_deref(), &root) {
+            Some(sha) => options.rev = Some(sha),
+            None => return Err(BlameError::NoCommitsBefore(until.clone())),
+        }
+    }
+
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    // Files in submodules are blamed from their own repository's root
+    let mut groups: HashMap<&Path, Vec<(String, Vec<String>)>> = HashMap::new();
+    for (file, ranges) in &tracked_files {
+        let file_root = file_roots.get(file).unwrap_or(&root);
+        groups
+            .entry(file_root)
+            .or_default()
+            .push((file.clone(), ranges.clone()));
+    }
+    let (mut stats, mut file_stats, mut failed): Collected = Default::default();
+    for (group_root, group) in groups.iter().filter(|_| has_history) {
+        let mut group_options = options.clone();
+        if *group_root != root && !explicit_ignore_revs {
+            group_options.ignore_revs_file = vcs
+                .default_ignore_revs_file(group_root)
+                .filter(|_| options.backend == Backend::Subprocess);
+        }
+        let (group_stats, group_file_stats, group_failed) =
+            collect_all_blame_stats(vcs.as_ref(), group, group_root, &group_options, jobs);
+        for (author, author_stats) in group_stats {
+            stats.entry(author).or_default().merge(author_stats);
+        }
+        file_stats.extend(group_file_stats);
+        failed.extend(group_failed);
+    }
+    for (file, ranges) in &untracked_files {
+        let mut this_file: HashMap<String, AuthorStats> = HashMap::new();
+        if let Err(e) = collect_untracked_stats(file, ranges, &options, &mut this_file) {
+            warn(
+                &options,
+                format_args!("Could not process '{}': {}", file, e),
+            );
+            failed.push(file.clone());
+            continue;
+        }
+        add_file_stats(&mut stats, &this_file, &options);
+        if options.per_file {
+            file_stats.insert(file.clone(), this_file);
+        }
+    }
+    if options.no_merges {
+        let shas: HashSet<&str> = stats
+            .values()
+            .flat_map(|s| s.commits.iter().map(String::as_str))
+            .collect();
+        let merges = vcs.merge_commits(&shas.into_iter().collect::<Vec<_>>(), &root);
+        for author_stats in stats
+            .values_mut()
+            .chain(file_stats.values_mut().flat_map(|s| s.values_mut()))
+        {
+            author_stats.commits.retain(|sha| !merges.contains(sha));
+        }
+    }
+
+    let mut aliases = if options.merge_by_email {
+        names_for_email_keys(&stats)
+    } else {
+        vcs.author_aliases(&stats, &root)
+    };
+    let mut stats = rename_authors(stats, &aliases);
+    if options.normalize_names {
+        let normalized = normalized_names(&stats);
+        stats = rename_authors(stats, &normalized);
+        // Chain through so per-file stats end up under the same names
+        for name in aliases.values_mut() {
+            if let Some(display) = normalized.get(name) {
+                *name = display.clone();
+            }
+        }
+        aliases.extend(normalized);
+    }
+    let file_stats = file_stats
+        .into_iter()
+        .map(|(file, stats)| (file, rename_authors(stats, &aliases)))
+        .collect();
+
+    Ok(BlameReport {
+        root: Some(root),
+        vcs: Some(vcs.name()),
+        files: files.into_iter().map(|(file, _)| file).collect(),
+        stats,
+        file_stats,
+        failed,
+        skipped,
+    })
+}
+
+/// First line of the commit message for each of `shas` in the repository at
+/// `root`, looked up in one batch. SHAs that can't be found are left out.
+pub fn commit_subjects(root: &Path, shas: &[&str]) -> HashMap<String, String> {
+    let mut shas = shas.to_vec();
+    shas.sort_unstable();
+    shas.dedup();
+    match detect_vcs(root) {
+        Some(vcs) => vcs.commit_subjects(&shas, root),
+        None => HashMap::new(),
+    }
+}
+
+/// Find the root of the git repository containing `path`.
+pub fn get_git_root(path: &Path) -> Option<PathBuf> {
+    repository_root(&GitBackend, path)
+}
+
+/// Print a warning to stderr unless [`BlameOptions::quiet`] is set.
+fn warn(options: &BlameOptions, message: fmt::Arguments) {
+    if !options.quiet {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+/// Detect the VCS for `path`, find its root, and check `options` against it.
+fn open_repository(
+    path: &Path,
+    options: &BlameOptions,
+) -> Result<(Box<dyn VcsBackend>, PathBuf), BlameError> {
+    let Some(vcs) = detect_vcs(path) else {
+        return Err(BlameError::NotARepository(path.to_path_buf()));
+    };
+    vcs.check_options(options)?;
+
+    let Some(root) = repository_root(vcs.as_ref(), path) else {
+        return Err(match vcs.name() {
+            "git" if !GitBackend::is_available() => BlameError::GitNotFound,
+            "hg" if !HgBackend::is_available() => BlameError::HgNotFound,
+            _ => BlameError::NotARepository(path.to_path_buf()),
+        });
+    };
+
+    if let Some(rev) = &options.rev
+        && !vcs.is_valid_rev(rev, &root)
+    {
+        return Err(BlameError::UnknownRevision(rev.clone()));
+    }
+
+    Ok((vcs, root))
+}
+
+/// Expand a pattern into paths, along with the `git blame -L` range from a
+/// trailing `:start-end` or `:line` suffix, if any. A leading `~`, `$VAR`
+/// references and `{a,b}` braces are expanded first, as a shell would for an
+/// unquoted pattern.
+pub fn expand_pattern(pattern: &str) -> (Vec<PathBuf>, Option<String>) {
+    // A path that really exists is taken literally, `~` and `$` included
+    let expanded;
+    let pattern = if Path::new(pattern).exists() {
+        pattern
+    } else {
+        expanded = expand_shell_references(pattern);
+        &expanded
+    };
+
+    // A path that really exists takes precedence over a line range suffix
+    if !Path::new(pattern).exists()
+        && let Some((path, range)) = split_line_range(pattern)
+    {
+        return (expand_alternatives(path), Some(range));
+    }
+
+    (expand_alternatives(pattern), None)
+}
+
+/// Read the `.blameignore` at `root`, warning about (and skipping) lines
+/// that aren't valid patterns. A missing file ignores nothing.
+fn load_blameignore(root: &Path, options: &BlameOptions) -> BlameIgnore {
+    let path = root.join(BLAMEIGNORE_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            warn(
+                options,
+                format_args!("Could not read {}: {}", path.display(), e),
+            );
+            String::new()
+        }
+    };
+    let (blameignore, invalid) = BlameIgnore::parse(&contents);
+    for line in invalid {
+        warn(
+            options,
+            format_args!(
+                "Ignoring invalid pattern '{}' in {}",
+                line, BLAMEIGNORE_FILE
+            ),
+        );
+    }
+    blameignore
+}
+
+/// A pattern removing files from the set to blame: a glob, or a directory
+/// excluding everything beneath it.
+struct Exclusion {
+    /// The pattern as given, for [`SkipReason::Excluded`].
+    source: String,
+    /// One glob per `{a,b}` alternative, and the directories among them.
+    patterns: Vec<Pattern>,
+    dirs: Vec<PathBuf>,
+}
+
+impl Exclusion {
+    fn new(pattern: &str) -> Result<Exclusion, BlameError> {
+        // Expanded files are absolute, so anchor relative patterns at the cwd
+        let cwd = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| cwd.canonicalize().ok());
+        let mut patterns = Vec::new();
+        let mut dirs = Vec::new();
+        for alternative in expand_braces(&expand_shell_references(pattern)) {
+            let absolute = cwd
+                .as_ref()
+                .map_or_else(|| PathBuf::from(&alternative), |cwd| cwd.join(&alternative));
+            let glob = Pattern::new(&absolute.to_string_lossy())
+                .map_err(|_| BlameError::InvalidExcludePattern(pattern.to_string()))?;
+            patterns.push(glob);
+            if let Some(dir) = Path::new(&alternative)
+                .canonicalize()
+                .ok()
+                .filter(|path| path.is_dir())
+            {
+                dirs.push(dir);
+            }
+        }
+        Ok(Exclusion {
+            source: pattern.to_string(),
+            patterns,
+            dirs,
+        })
+    }
+
+    fn matches(&self, file: &Path) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches_path(file))
+            || self.dirs.iter().any(|dir| file.starts_with(dir))
+    }
+}
+
+/// Whether `file` is no more than `max_depth` levels below `dir`. `ls-files`
+/// style listings are always recursive, so depth is enforced afterwards.
+fn within_depth(file: &Path, dir: &Path, max_depth: Option<usize>) -> bool {
+    let Some(max_depth) = max_depth else {
+        return true;
+    };
+    file.strip_prefix(dir)
+        .map_or(true, |relative| relative.components().count() <= max_depth)
+}
+
+/// How much of a file [`is_binary`] looks through, as git does.
+const BINARY_CHECK_BYTES: usize = 8000;
+
+/// Whether the working tree copy of `file` looks binary: a NUL byte among
+/// its first [`BINARY_CHECK_BYTES`], as git checks. Files that can't be read
+/// are left for blame to report on.
+fn is_binary(file: &Path) -> bool {
+    let Ok(handle) = std::fs::File::open(file) else {
+        return false;
+    };
+    let mut head = Vec::new();
+    match handle
+        .take(BINARY_CHECK_BYTES as u64)
+        .read_to_end(&mut head)
+    {
+        Ok(_) => head.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// Whether `pattern` has glob wildcards. Braces don't count: like a shell,
+/// blame expands `{a,b}` to separate paths first, and each of those is then
+/// checked (and warned about) as if it had been given on its own.
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Split `foo.rs:40-80` into `("foo.rs", "40,80")` and `foo.rs:42` into
+/// `("foo.rs", "42,42")`.
+fn split_line_range(pattern: &str) -> Option<(&str, String)> {
+    let (path, suffix) = pattern.rsplit_once(':')?;
+    let (start, end) = suffix.split_once('-').unwrap_or((suffix, suffix));
+    let start: usize = start.parse().ok()?;
+    let end: usize = end.parse().ok()?;
+    if path.is_empty() || start == 0 || end < start {
+        return None;
+    }
+    Some((path, format!("{},{}", start, end)))
+}
+
+/// Expand a leading `~` or `~user` to a home directory and `$VAR` or `${VAR}`
+/// to the variable's value. Unset variables, a `$` that doesn't start a name,
+/// and anything inside a `[...]` character class are left alone. In a glob,
+/// expanded values are escaped so their own `*`, `?` and `[` match literally.
+fn expand_shell_references(pattern: &str) -> String {
+    expand_references_with(pattern, &|name| std::env::var_os(name))
+}
+
+/// [`expand_shell_references`] with variables (`HOME` included) read through
+/// `var` rather than from the environment.
+fn expand_references_with(pattern: &str, var: &dyn Fn(&str) -> Option<OsString>) -> String {
+    let escape = |value: &str| {
+        if is_glob(pattern) {
+            Pattern::escape(value)
+        } else {
+            value.to_string()
+        }
+    };
+    let mut expanded = String::new();
+    let mut rest = pattern;
+
+    if let Some(after) = pattern.strip_prefix('~') {
+        let (user, tail) = after.split_at(after.find('/').unwrap_or(after.len()));
+        if let Some(home) = home_dir(user, var) {
+            expanded.push_str(&escape(&home.to_string_lossy()));
+            rest = tail;
+        }
+    }
+
+    let mut in_class = false;
+    while let Some(c) = rest.chars().next() {
+        if c == '$'
+            && !in_class
+            && let Some((name, len)) = variable_reference(&rest[1..])
+            && let Some(value) = var(name)
+        {
+            expanded.push_str(&escape(&value.to_string_lossy()));
+            rest = &rest[1 + len..];
+            continue;
+        }
+        match c {
+            '[' => in_class = true,
+            ']' => in_class = false,
+            _ => {}
+        }
+        expanded.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    expanded
+}
+
+/// The variable name at the start of `text` (just after a `$`), either bare or
+/// in braces, along with how many bytes the reference takes up.
+fn variable_reference(text: &str) -> Option<(&str, usize)> {
+    let is_name = |name: &str| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if let Some(braced) = text.strip_prefix('{') {
+        let name = &braced[..braced.find('}')?];
+        return is_name(name).then_some((name, name.len() + 2));
+    }
+    let len = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len());
+    let name = &text[..len];
+    is_name(name).then_some((name, len))
+}
+
+/// The home directory of `user`, or of whoever is running blame when `user`
+/// is empty (from `HOME`, read through `var`). Other users' are looked up in
+/// `/etc/passwd`.
+fn home_dir(user: &str, var: &dyn Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    if user.is_empty() {
+        return var("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+    }
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|entry| {
+        let fields: Vec<&str> = entry.split(':').collect();
+        (fields.len() >= 6 && fields[0] == user).then(|| PathBuf::from(fields[5]))
+    })
+}
+
+/// Absolute path of the file `pattern` names, less any line range, if it
+/// doesn't exist but the directory it would be in does.
+fn missing_file_path(pattern: &str) -> Option<PathBuf> {
+    let path = Path::new(split_line_range(pattern).map_or(pattern, |(path, _)| path));
+    if path.exists() {
+        return None;
+    }
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(path.file_name()?))
+}
+
+/// Glob every alternative of a brace pattern, keeping the first match of any
+/// path that more than one of them matches.
+fn expand_alternatives(pattern: &str) -> Vec<PathBuf> {
+    if Path::new(pattern).exists() {
+        return expand_glob(pattern);
+    }
+    let mut seen = HashSet::new();
+    expand_braces(pattern)
+        .iter()
+        .flat_map(|alternative| expand_glob(alternative))
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+/// Most paths braces may expand to, so a pattern like `{1..999999999}` can't
+/// exhaust memory.
+const MAX_BRACE_ALTERNATIVES: usize = 1024;
+
+/// Expand shell-style braces: `{a,b}` lists alternatives and `{1..3}` a
+/// range of numbers, and braces can nest. Braces that are neither, like `{a}`,
+/// are kept as they are, as is a pattern that would expand to more than
+/// [`MAX_BRACE_ALTERNATIVES`] paths.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let mut expanded = Vec::new();
+    if expand_braces_into(pattern, &mut expanded) {
+        expanded
+    } else {
+        vec![pattern.to_string()]
+    }
+}
+
+/// Add the expansions of `pattern` to `expanded`, giving up (with `false`)
+/// rather than go past [`MAX_BRACE_ALTERNATIVES`].
+fn expand_braces_into(pattern: &str, expanded: &mut Vec<String>) -> bool {
+    for (open, _) in pattern.match_indices('{') {
+        let Some(close) = matching_brace(pattern, open) else {
+            continue;
+        };
+        let Some(alternatives) = brace_alternatives(&pattern[open + 1..close]) else {
+            continue;
+        };
+        let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+        // Alternatives and the suffix may hold more braces of their own
+        return alternatives.iter().all(|alternative| {
+            expand_braces_into(&format!("{}{}{}", prefix, alternative, suffix), expanded)
+        });
+    }
+    if expanded.len() == MAX_BRACE_ALTERNATIVES {
+        return false;
+    }
+    expanded.push(pattern.to_string());
+    true
+}
+
+/// Index of the `}` closing the `{` at `open`.
+fn matching_brace(pattern: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in pattern[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open + i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// What the inside of a pair of braces expands to: its top-level
+/// comma-separated parts, or the numbers in a `start..end` range. Ranges keep
+/// leading zeros (`{01..10}`), as in bash.
+fn brace_alternatives(inner: &str) -> Option<Vec<String>> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(inner[start..i].to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !parts.is_empty() {
+        parts.push(inner[start..].to_string());
+        return Some(parts);
+    }
+
+    let (from, to) = inner.split_once("..")?;
+    let (first, last): (i64, i64) = (from.parse().ok()?, to.parse().ok()?);
+    if first.abs_diff(last) >= MAX_BRACE_ALTERNATIVES as u64 {
+        return None;
+    }
+    let padded =
+        |n: &str| n.trim_start_matches('-').len() > 1 && n.trim_start_matches('-').starts_with('0');
+    let width = if padded(from) || padded(to) {
+        from.len().max(to.len())
+    } else {
+        0
+    };
+    let numbers: Vec<i64> = if first <= last {
+        (first..=last).collect()
+    } else {
+        (last..=first).rev().collect()
+    };
+    Some(
+        numbers
+            .into_iter()
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect(),
+    )
+}
+
+fn expand_glob(pattern: &str) -> Vec<PathBuf> {
+    let literal_path = Path::new(pattern);
+    if literal_path.exists() {
+        return vec![
+            literal_path
+                .canonicalize()
+                .unwrap_or(literal_path.to_path_buf()),
+        ];
+    }
+
+    // Try as a glob pattern
+    match glob(pattern) {
+        Ok(paths) => paths
+            .filter_map(|p| p.ok())
+            .filter_map(|p| p.canonicalize().ok())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Stats for each blamed file, keyed by file.
+type FileStats = HashMap<String, HashMap<String, AuthorStats>>;
+
+/// Everything [`collect_all_blame_stats`] gathers: overall stats, per-file
+/// stats, and the files that couldn't be blamed.
+type Collected = (HashMap<String, AuthorStats>, FileStats, Vec<String>);
+
+/// Fewest files [`BlameOptions::progress`] shows a progress bar for.
+const PROGRESS_MIN_FILES: usize = 20;
+
+/// Co-authors of every commit looked up so far, so each is only asked for once.
+type CoAuthorCache = Mutex<HashMap<String, Vec<CoAuthor>>>;
+
+/// Blame `files` across up to `jobs` threads. Each thread keeps its own stats and
+/// the results are merged afterwards, so output doesn't depend on scheduling.
+/// Per-file stats are only kept when `options.per_file` is set.
+fn collect_all_blame_stats(
+    vcs: &dyn VcsBackend,
+    files: &[(String, Vec<String>)],
+    root: &Path,
+    options: &BlameOptions,
+    jobs: usize,
+) -> Collected {
+    let next_file = AtomicUsize::new(0);
+    let co_authors = CoAuthorCache::default();
+    // Hidden bars draw nothing, and indicatif hides them itself when stderr
+    // isn't a terminal
+    let progress = if options.progress && files.len() >= PROGRESS_MIN_FILES {
+        ProgressBar::new(files.len() as u64).with_style(
+            ProgressStyle::with_template("{bar:30} {pos}/{len} files, ETA {eta}")
+                .expect("valid progress template"),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    let workers = jobs.clamp(1, files.len().max(1));
+
+    let partials: Vec<Collected> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut stats: HashMap<String, AuthorStats> = HashMap::new();
+                    let mut file_stats: FileStats = HashMap::new();
+                    let mut failed = Vec::new();
+                    while let Some((file, ranges)) =
+                        files.get(next_file.fetch_add(1, Ordering::Relaxed))
+                    {
+                        if !options.per_file && !options.per_file_weight {
+                            if let Err(e) = collect_file_stats(
+                                vcs,
+                                file,
+                                ranges,
+                                root,
+                                options,
+                                &co_authors,
+                                &mut stats,
+                            ) {
+                                progress.suspend(|| {
+                                    warn(
+                                        options,
+                                        format_args!("Could not process '{}': {}", file, e),
+                                    )
+                                });
+                                failed.push(file.clone());
+                            }
+                            progress.inc(1);
+                            continue;
+                        }
+
+                        let mut this_file: HashMap<String, AuthorStats> = HashMap::new();
+                        if let Err(e) = collect_file_stats(
+                            vcs,
+                            file,
+                            ranges,
+                            root,
+                            options,
+                            &co_authors,
+                            &mut this_file,
+                        ) {
+                            progress.suspend(|| {
+                                warn(options, format_args!("Could not process '{}': {}", file, e))
+                            });
+                            failed.push(file.clone());
+                            progress.inc(1);
+                            continue;
+                        }
+                        progress.inc(1);
+                        add_file_stats(&mut stats, &this_file, options);
+                        if options.per_file {
+                            file_stats.insert(file.clone(), this_file);
+                        }
+                    }
+                    (stats, file_stats, failed)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    progress.finish_and_clear();
+
+    let mut stats: HashMap<String, AuthorStats> = HashMap::new();
+    let mut file_stats: FileStats = HashMap::new();
+    let mut failed = Vec::new();
+    for (partial, partial_files, partial_failed) in partials {
+        for (author, author_stats) in partial {
+            stats.entry(author).or_default().merge(author_stats);
+        }
+        file_stats.extend(partial_files);
+        failed.extend(partial_failed);
+    }
+    failed.sort();
+
+    (stats, file_stats, failed)
+}
+
+/// Display names for stats keyed by email with [`BlameOptions::merge_by_email`]:
+/// each author's most common name, plus the email when two authors would
+/// otherwise share a name.
+fn names_for_email_keys(stats: &HashMap<String, AuthorStats>) -> HashMap<String, String> {
+    let names: HashMap<&str, &str> = stats
+        .iter()
+        .map(|(key, s)| (key.as_str(), s.most_common_name().unwrap_or(key)))
+        .collect();
+    let mut uses: HashMap<&str, usize> = HashMap::new();
+    for name in names.values() {
+        *uses.entry(name).or_default() += 1;
+    }
+
+    names
+        .iter()
+        .filter(|(key, name)| key != name)
+        .map(|(key, name)| {
+            let display = if uses[name] > 1 {
+                format!("{} <{}>", name, key)
+            } else {
+                name.to_string()
+            };
+            (key.to_string(), display)
+        })
+        .collect()
+}
+
+/// Display names for [`BlameOptions::normalize_names`]: each author whose name
+/// matches another's once case and whitespace are ignored (or who has stray
+/// whitespace) maps to the spelling with the most lines, ties going to the
+/// alphabetically first, with its whitespace tidied up.
+fn normalized_names(stats: &HashMap<String, AuthorStats>) -> HashMap<String, String> {
+    let tidy = |name: &str| name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut groups: HashMap<String, Vec<(&String, usize)>> = HashMap::new();
+    for (author, author_stats) in stats {
+        groups
+            .entry(tidy(author).to_lowercase())
+            .or_default()
+            .push((author, author_stats.lines()));
+    }
+
+    let mut names = HashMap::new();
+    for variants in groups.values() {
+        let Some((winner, _)) = variants
+            .iter()
+            .max_by(|(a_name, a), (b_name, b)| a.cmp(b).then(b_name.cmp(a_name)))
+        else {
+            continue;
+        };
+        let display = tidy(winner);
+        for (author, _) in variants {
+            if **author != display {
+                names.insert(author.to_string(), display.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Re-key `stats` through `names` (old name to new name), merging authors that
+/// end up with the same name.
+pub fn rename_authors(
+    stats: HashMap<String, AuthorStats>,
+    names: &HashMap<String, String>,
+) -> HashMap<String, AuthorStats> {
+    if names.is_empty() {
+        return stats;
+    }
+    let mut renamed: HashMap<String, AuthorStats> = HashMap::new();
+    for (author, author_stats) in stats {
+        let key = names.get(&author).cloned().unwrap_or(author);
+        renamed.entry(key).or_default().merge(author_stats);
+    }
+    renamed
+}
+
+/// Blame one file (restricted to `ranges`, as `git blame -L` arguments) and add
+/// its lines to `stats`. `options.until` and `options.function` are resolved by
+/// [`blame_paths`] and ignored here.
+pub fn collect_blame_stats(
+    vcs: &dyn VcsBackend,
+    file: &str,
+    ranges: &[String],
+    root: &Path,
+    options: &BlameOptions,
+    stats: &mut HashMap<String, AuthorStats>,
+) -> Result<(), BlameError> {
+    let co_authors = CoAuthorCache::default();
+    collect_file_stats(vcs, file, ranges, root, options, &co_authors, stats)
+}
+
+/// Fold one file's stats into `stats`, first scaling them down to that file's
+/// share of the weight under [`BlameOptions::per_file_weight`].
+fn add_file_stats(
+    stats: &mut HashMap<String, AuthorStats>,
+    this_file: &HashMap<String, AuthorStats>,
+    options: &BlameOptions,
+) {
+    let file_weight: f64 = this_file.values().map(AuthorStats::weighted_lines).sum();
+    for (author, author_stats) in this_file {
+        let mut author_stats = author_stats.clone();
+        if options.per_file_weight && file_weight > 0.0 {
+            author_stats.scale_weight(1.0 / file_weight);
+        }
+        stats.entry(author.clone()).or_default().merge(author_stats);
+    }
+}
+
+/// [`collect_blame_stats`], sharing co-author lookups through `co_authors`.
+fn collect_file_stats(
+    vcs: &dyn VcsBackend,
+    file: &str,
+    ranges: &[String],
+    root: &Path,
+    options: &BlameOptions,
+    co_authors: &CoAuthorCache,
+    stats: &mut HashMap<String, AuthorStats>,
+) -> Result<(), BlameError> {
+    let now = chrono::Utc::now().timestamp();
+    let hunks = vcs.blame(file, ranges, root, options)?;
+    let co_authors = if options.co_authors {
+        lookup_co_authors(vcs, &hunks, root, co_authors)
+    } else {
+        HashMap::new()
+    };
+    let key = |hunk: &BlameHunk| match &hunk.mail {
+        Some(mail) if options.merge_by_email => mail.trim().to_lowercase(),
+        _ => hunk.author.clone(),
+    };
+
+    for mut hunk in hunks {
+        if hunk.is_uncommitted() {
+            if !options.include_uncommitted {
+                continue;
+            }
+            hunk.author = UNCOMMITTED_AUTHOR.to_string();
+            hunk.mail = None;
+            // Some git versions report epoch 0 for uncommitted lines
+            if hunk.time <= 0 {
+                hunk.time = now;
+            }
+        }
+        let weight = match options.decay {
+            Some(half_life) => decay_weight(now - hunk.time, half_life),
+            None => 1.0,
+        };
+
+        // Leave out co-authors who are the author under another spelling
+        let credited: Vec<&CoAuthor> = co_authors
+            .get(&hunk.sha)
+            .into_iter()
+            .flatten()
+            .filter(|(name, mail)| *name != hunk.author && (mail.is_none() || *mail != hunk.mail))
+            .collect();
+        let share = weight / (credited.len() + 1) as f64;
+        for (name, mail) in credited {
+            let co_hunk = BlameHunk {
+                author: name.clone(),
+                mail: mail.clone(),
+                time: hunk.time,
+                sha: hunk.sha.clone(),
+                lines: hunk.lines,
+            };
+            stats
+                .entry(key(&co_hunk))
+                .or_default()
+                .add_hunk(file, co_hunk, share);
+        }
+        stats
+            .entry(key(&hunk))
+            .or_default()
+            .add_hunk(file, hunk, share);
+    }
+    Ok(())
+}
+
+/// Co-authors of the commits behind `hunks`, asking the VCS only about commits
+/// not already in `cache`.
+fn lookup_co_authors(
+    vcs: &dyn VcsBackend,
+    hunks: &[BlameHunk],
+    root: &Path,
+    cache: &CoAuthorCache,
+) -> HashMap<String, Vec<CoAuthor>> {
+    let shas: HashSet<&str> = hunks
+        .iter()
+        .filter(|hunk| !hunk.is_uncommitted())
+        .map(|hunk| hunk.sha.as_str())
+        .collect();
+    let missing: Vec<&str> = {
+        let cache = cache.lock().unwrap();
+        shas.iter()
+            .copied()
+            .filter(|sha| !cache.contains_key(*sha))
+            .collect()
+    };
+    let mut found = vcs.co_authors(&missing, root);
+
+    let mut cache = cache.lock().unwrap();
+    for sha in missing {
+        let co_authors = found.remove(sha).unwrap_or_default();
+        cache.insert(sha.to_string(), co_authors);
+    }
+    shas.iter()
+        .filter_map(|sha| Some((sha.to_string(), cache.get(*sha)?.clone())))
+        .filter(|(_, co_authors)| !co_authors.is_empty())
+        .collect()
+}
+
+/// Count every line of an untracked file (or those in `ranges`) under
+/// [`UNCOMMITTED_AUTHOR`], as if it were one uncommitted hunk written now.
+fn collect_untracked_stats(
+    file: &str,
+    ranges: &[String],
+    options: &BlameOptions,
+    stats: &mut HashMap<String, AuthorStats>,
+) -> Result<(), BlameError> {
+    let contents = std::fs::read(file).map_err(BlameError::Io)?;
+    let ranges = ranges
+        .iter()
+        .map(|range| vcs::parse_range(range))
+        .collect::<Result<Vec<_>, _>>()?;
+    let contents = String::from_utf8_lossy(&contents);
+    let contents: Vec<&str> = contents.lines().collect();
+    let uncredited = vcs::uncredited_lines(file, &contents, options);
+    let lines = (0..contents.len())
+        .filter(|&index| {
+            vcs::lines_in_ranges(index + 1, 1, &ranges) == 1
+                && !uncredited.get(index).copied().unwrap_or(false)
+        })
+        .count();
+    if lines == 0 {
+        return Ok(());
+    }
+
+    let hunk = BlameHunk {
+        author: UNCOMMITTED_AUTHOR.to_string(),
+        mail: None,
+        time: chrono::Utc::now().timestamp(),
+        sha: "0".repeat(40),
+        lines,
+    };
+    stats
+        .entry(hunk.author.clone())
+        .or_default()
+        .add_hunk(file, hunk, 1.0);
+    Ok(())
+}
+
+/// Exponential decay: 1.0 for a line written now, 0.5 after one `half_life`.
+/// Timestamps in the future count as brand new.
+fn decay_weight(age_secs: i64, half_life: Duration) -> f64 {
+    let age = age_secs.max(0) as f64;
+    0.5f64.powf(age / half_life.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expand `pattern` with only the variables in `vars` set.
+    fn expand(pattern: &str, vars: &[(&str, &str)]) -> String {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        expand_references_with(pattern, &|name| vars.get(name).map(OsString::from))
+    }
+
+    #[test]
+    fn home_and_home_variable() {
+        let home = [("HOME", "/home/tester")];
+        assert_eq!(expand("~/src", &home), "/home/tester/src");
+        assert_eq!(expand("~", &home), "/home/tester");
+        assert_eq!(expand("$HOME/src", &home), "/home/tester/src");
+        // Only a leading ~ is a home directory
+        assert_eq!(expand("src/~backup", &home), "src/~backup");
+    }
+
+    #[test]
+    fn braced_variables() {
+        assert_eq!(
+            expand("src/${DIR}s/*.rs", &[("DIR", "lib")]),
+            "src/libs/*.rs"
+        );
+    }
+
+    #[test]
+    fn unset_variables_are_left_alone() {
+        assert_eq!(expand("$UNSET/x", &[]), "$UNSET/x");
+        assert_eq!(expand("${UNSET}", &[]), "${UNSET}");
+        assert_eq!(expand("~/x", &[]), "~/x");
+    }
+
+    #[test]
+    fn literal_dollars_are_left_alone() {
+        let vars = [("X", "y")];
+        assert_eq!(expand("cost$", &vars), "cost$");
+        assert_eq!(expand("$1.txt", &vars), "$1.txt");
+        assert_eq!(expand("${}", &vars), "${}");
+        assert_eq!(expand("[$X]x", &vars), "[$X]x");
+    }
+
+    #[test]
+    fn braces_list_alternatives() {
+        assert_eq!(expand_braces("src/{a,b}.rs"), ["src/a.rs", "src/b.rs"]);
+        assert_eq!(expand_braces("{a,}x"), ["ax", "x"]);
+    }
+
+    #[test]
+    fn nested_braces() {
+        assert_eq!(
+            expand_braces("{src/{core,cli},tests}/*.rs"),
+            ["src/core/*.rs", "src/cli/*.rs", "tests/*.rs"]
+        );
+    }
+
+    #[test]
+    fn several_brace_groups() {
+        assert_eq!(
+            expand_braces("{a,b}/{x,y}.rs"),
+            ["a/x.rs", "a/y.rs", "b/x.rs", "b/y.rs"]
+        );
+    }
+
+    #[test]
+    fn numeric_ranges() {
+        assert_eq!(expand_braces("f{1..3}.txt"), ["f1.txt", "f2.txt", "f3.txt"]);
+        assert_eq!(expand_braces("f{3..1}"), ["f3", "f2", "f1"]);
+        assert_eq!(expand_braces("f{08..10}"), ["f08", "f09", "f10"]);
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        assert_eq!(expand_braces("{a}.rs"), ["{a}.rs"]);
+        assert_eq!(expand_braces("{a,b"), ["{a,b"]);
+        assert_eq!(expand_braces("{1..x}"), ["{1..x}"]);
+    }
+
+    #[test]
+    fn oversized_braces_are_kept() {
+        assert_eq!(expand_braces("f{1..999999999}"), ["f{1..999999999}"]);
+        assert_eq!(expand_braces("{1..40}{1..40}"), ["{1..40}{1..40}"]);
+        assert_eq!(expand_braces("{1..1024}").len(), 1024);
+    }
+
+    #[test]
+    fn git_root_of_a_file_and_a_directory() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .canonicalize()
+            .unwrap();
+        let Some(found) = get_git_root(&root.join("src/lib.rs")) else {
+            return; // Not built from a git checkout
+        };
+        assert!(root.starts_with(found.canonicalize().unwrap()));
+        assert_eq!(get_git_root(&root.join("src")), Some(found));
+    }
+
+    #[test]
+    fn exclusions_expand_braces() {
+        let exclusion = Exclusion::new("/repo/{h,f1}.txt").unwrap();
+        assert!(exclusion.matches(Path::new("/repo/h.txt")));
+        assert!(exclusion.matches(Path::new("/repo/f1.txt")));
+        assert!(!exclusion.matches(Path::new("/repo/f2.txt")));
+    }
+
+    #[test]
+    fn values_in_globs_match_literally() {
+        let vars = [("GLOBBY", "a*b")];
+        assert_eq!(expand("$GLOBBY/*.rs", &vars), "a[*]b/*.rs");
+        assert_eq!(expand("$GLOBBY/x.rs", &vars), "a*b/x.rs");
+    }
+}
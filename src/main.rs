@@ -1,12 +1,26 @@
+use blame::github::{
+    LoginCache, cache_dir, find_github_remote, get_current_github_user, get_latest_release_tag,
+    resolve_github_logins, set_gh_program,
+};
+use blame::{
+    AuthorStats, Backend, BlameError, BlameOptions, blame_paths, commit_subjects, git_command,
+    month_of, rename_authors, set_git_program,
+};
+use chrono::format::{Item, StrftimeItems};
 use chrono::{DateTime, TimeZone, Utc};
-use clap::Parser;
-use glob::glob;
-use std::collections::{HashMap, HashSet};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, IsTerminal, LineWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
 #[derive(Parser)]
-#[command(name = "blame")]
+#[command(name = "blame", version)]
 #[command(about = "Find out who is responsible for a file or folder")]
 #[command(after_help = "\
 EXAMPLES:
@@ -14,274 +28,2504 @@ EXAMPLES:
     blame foo.rs bar.rs       Blame multiple files
     blame src/                Blame all files in a directory
     blame \"**/*.rs\"           Blame all Rust files (glob pattern)
+    blame \"src/{core,cli}/*.rs\"   Blame the Rust files in two directories
+    blame foo.rs:40-80        Blame only lines 40 to 80 of a file
+    blame --function main foo.rs   Blame only the main function
     blame -v src/             Show all contributors with percentages
+    blame --by-commits -v src/    Rank contributors by share of commits
+    blame --decay 90d -v src/     Favor recently touched lines (90-day half-life)
+    blame --per-file src/     Show which files each contributor owns
+    blame --compare v1.0..v2.0 src/   See how ownership shifted between releases
+    blame --bus-factor src/   Count how many people own most of the code
+    blame --date iso -v src/  Show last-touched dates as YYYY-MM-DD
+    blame \"src/**/*.rs\" \"!src/bindings.rs\"   Leave a generated file out
+    blame --dry-run --debug \"src/**\"   See which files a pattern picks up
+    git diff --name-only main | blame -   Blame the files a branch touches
+    git diff main | blame --stdin-diff --rev main -v   Find who owned the lines a branch changes
     blame --gh src/           Output GitHub usernames (for PR reviewers)
     blame --gh --only-name src/   Output just the username (for scripts)
+    blame --only-name -v -0 src/ | xargs -0 -n1 echo   Loop over every name safely
+    blame --reviewers src/    Output \"@login1, @login2\" for a PR body
+    blame --codeowners --top 2 .   Draft a CODEOWNERS file from ownership
+    blame --json -v src/      Output all contributors as JSON
+    blame --csv -v src/       Output all contributors as CSV
+    blame --markdown -v -o OWNERS.md src/   Write a report to a file
+    blame --exclude-author \"dependabot*\" src/   Leave bots out of the tally
+
+IGNORING FILES:
+    Files matching the gitignore-style patterns in .blameignore at the
+    repository root (generated code, vendored dependencies, lockfiles) are left
+    out, e.g.
+
+        vendor/
+        *.lock
+        /src/generated/**
+
+    Pass --no-ignore to blame them anyway.
+
+CONFIGURATION:
+    Defaults for --gh, --sort, --no-bots, --color, --top, and --bot-pattern can
+    be set in $XDG_CONFIG_HOME/blame/config.toml (~/.config/blame/config.toml)
+    and in .blame.toml at the repository root, e.g.
+
+        gh = true
+        sort = \"recent\"
+        no_bots = true
+
+    Flags on the command line win over .blame.toml, which wins over the user
+    config, which wins over the built-in defaults.
+
+EXIT STATUS:
+    0    Success
+    1    No results, or any other error
+    2    Invalid command-line usage, or an option the backend doesn't support
+    3    Path is not in a git or Mercurial repository
+    4    Unknown --rev, or no commits before --until
+    5    Blame failed or produced unreadable output
+    6    No tracked files matched
+    7    The GitHub repository couldn't be determined (--gh)
+    8    An ownership check failed (--fail-under, --fail-if-single-owner)
+    127  git (or hg) is not installed
 ")]
 struct Args {
-    /// Files, folders, or glob patterns to analyze (e.g., foo.rs bar.rs "**/*.rs")
-    #[arg(required_unless_present = "upgrade")]
+    /// Files, folders, or glob patterns to analyze (e.g., foo.rs bar.rs "**/*.rs");
+    /// append :start-end or :line to a file to blame only those lines. Use - to
+    /// read newline-separated paths from stdin. A leading ~, $VARIABLES and
+    /// {a,b} or {1..3} braces are expanded even in quoted patterns
+    #[arg(required_unless_present_any = ["upgrade", "check_update", "files_from", "files_from0", "stdin_diff"])]
     patterns: Vec<String>,
 
+    /// Also blame the newline-separated paths listed in this file (- for stdin)
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<String>,
+
+    /// Also blame the NUL-separated paths listed in this file (- for stdin), as
+    /// printed by `git diff -z --name-only`
+    #[arg(long, value_name = "FILE", conflicts_with = "files_from")]
+    files_from0: Option<String>,
+
+    /// Read a unified diff from stdin and blame only the lines it changes or
+    /// removes, as of its base: --rev, or HEAD by default. Lines the diff only
+    /// adds have no previous owner and are left out
+    #[arg(long, conflicts_with_all = ["until", "compare", "include_untracked"])]
+    stdin_diff: bool,
+
     /// Show detailed breakdown by contributor
     #[arg(short, long)]
     verbose: bool,
 
-    /// Output GitHub usernames instead of git author names
+    /// Don't print warnings (unmatched patterns, untracked or unreadable files);
+    /// errors are still reported
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Output GitHub usernames instead of git author names (via the GitHub API
+    /// with $GH_TOKEN or $GITHUB_TOKEN, or else the gh CLI)
     #[arg(long)]
     gh: bool,
 
+    /// GitHub host to query (defaults to the host of the GitHub remote)
+    #[arg(long, value_name = "HOST")]
+    gh_host: Option<String>,
+
+    /// Git remote naming the GitHub repository for --gh (defaults to upstream,
+    /// then origin, then the first remote on GitHub)
+    #[arg(long, value_name = "NAME")]
+    remote: Option<String>,
+
+    /// Print diagnostics to stderr, such as which remote --gh used and which
+    /// matched files were skipped and why
+    #[arg(long)]
+    debug: bool,
+
+    /// List the files that would be blamed, and how many, without blaming them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Don't read or write the on-disk GitHub login cache
+    #[arg(long)]
+    no_cache: bool,
+
     /// Only output the name (for use in scripts)
     #[arg(long)]
     only_name: bool,
 
+    /// With --only-name, end each name with a NUL byte instead of a newline,
+    /// for `xargs -0`
+    #[arg(short = '0', long, requires = "only_name")]
+    null: bool,
+
+    /// Output contributor stats as JSON
+    #[arg(long, conflicts_with = "only_name")]
+    json: bool,
+
+    /// Output contributor stats as CSV
+    #[arg(long, conflicts_with_all = ["only_name", "json"])]
+    csv: bool,
+
+    /// Output contributor stats as a GitHub-flavored Markdown table, with
+    /// @logins under --gh
+    #[arg(long, conflicts_with_all = ["only_name", "json", "csv"])]
+    markdown: bool,
+
+    /// Exclude an author from the results (case-insensitive, supports a trailing `*`)
+    #[arg(long, value_name = "NAME")]
+    exclude_author: Vec<String>,
+
+    /// Only show this author (repeatable; case-insensitive, supports a trailing
+    /// `*`, and with --gh matches logins or git names), still as a share of
+    /// everyone's lines. An author with nothing in scope is shown at 0.0%
+    #[arg(long, value_name = "NAME")]
+    author: Vec<String>,
+
+    /// Only attribute lines touched since this date (e.g. "2024-01-01", "3 months ago");
+    /// percentages then mean "lines last touched within the window"
+    #[arg(long, value_name = "DATE")]
+    since: Option<String>,
+
+    /// Blame the tree as of this date instead of the current checkout
+    #[arg(long, value_name = "DATE")]
+    until: Option<String>,
+
+    /// Ignore whitespace-only changes, attributing reformatted lines to the prior
+    /// substantive author
+    #[arg(short = 'w', long)]
+    ignore_whitespace: bool,
+
+    /// Don't credit anyone for empty or whitespace-only lines. Every share is
+    /// then of the lines with real content, so percentages shift
+    #[arg(long)]
+    ignore_blank_lines: bool,
+
+    /// Don't credit anyone for lines that are only a comment (//, #, --,
+    /// /* ... */ and the like, chosen by file extension). Comments are spotted
+    /// heuristically: blocks opened after code on the same line aren't followed
+    #[arg(long)]
+    ignore_comments: bool,
+
+    /// Only blame the named function, using git's funcname patterns (single file only)
+    #[arg(long, value_name = "NAME")]
+    function: Option<String>,
+
+    /// Blame files as of this revision, branch, or tag instead of the working tree
+    #[arg(long, value_name = "REV")]
+    rev: Option<String>,
+
+    /// Show how each contributor's share changed from one revision to another
+    /// (e.g. v1.0..v2.0; an empty side means HEAD), marking new and departed
+    /// contributors
+    #[arg(long, value_name = "REV1..REV2", value_parser = parse_compare,
+          conflicts_with_all = ["rev", "until", "include_untracked", "dry_run", "json", "csv",
+                                "markdown", "only_name", "reviewers", "per_file", "codeowners",
+                                "tree", "gh", "bus_factor", "concentration", "histogram"])]
+    compare: Option<(String, String)>,
+
+    /// Follow lines across renames and copies (-C -C -M); slower
+    #[arg(long)]
+    follow: bool,
+
+    /// Follow only the first parent of merge commits. Lines merged in from a
+    /// branch are credited to whoever made the merge, as history reads on the
+    /// mainline, instead of to the branch's commits
+    #[arg(long)]
+    first_parent: bool,
+
+    /// Ignore revisions listed in this file (defaults to .git-blame-ignore-revs when present)
+    #[arg(long, value_name = "PATH")]
+    ignore_revs_file: Option<PathBuf>,
+
+    /// How to compute blame: run the git binary, or use libgit2 in-process
+    /// (requires the `git2` cargo feature)
+    #[arg(long, value_enum, default_value_t = BackendChoice::Subprocess)]
+    backend: BackendChoice,
+
+    /// Attribute lines to their committer instead of their author. This answers
+    /// "who applied or rebased this" (e.g. a maintainer landing patches), not
+    /// "who wrote this"; every name shown is then a committer
+    #[arg(long)]
+    committer: bool,
+
+    /// Split each line's credit equally between its commit's author and anyone
+    /// in a Co-authored-by trailer, for teams that pair or mob
+    #[arg(long)]
+    co_authors: bool,
+
+    /// Compute ownership from each author's share of commits instead of lines
+    #[arg(long)]
+    by_commits: bool,
+
+    /// Weight each line by recency with this half-life (e.g. "90d", "12w", "1y"),
+    /// so a line that old counts half as much as one written today
+    #[arg(long, value_name = "HALF_LIFE", value_parser = parse_half_life, conflicts_with = "by_commits")]
+    decay: Option<Duration>,
+
+    /// Average each contributor's share of every file instead of counting
+    /// lines, so each file counts equally however long it is and one huge
+    /// generated file doesn't decide who owns a directory
+    #[arg(long, conflicts_with_all = ["by_commits", "per_file", "codeowners", "tree"])]
+    per_file_weight: bool,
+
+    /// How to order contributors (defaults to lines, or commits with --by-commits)
+    #[arg(long, value_enum)]
+    sort: Option<SortKey>,
+
+    /// Reverse the sort order
+    #[arg(long)]
+    reverse: bool,
+
+    /// Give up on a file whose blame takes longer than this many seconds, with
+    /// a warning (0 for no limit; doesn't apply to --backend git2)
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    timeout: u64,
+
+    /// git executable to run instead of the one on PATH [env: BLAME_GIT]
+    #[arg(long, value_name = "PATH")]
+    git_bin: Option<PathBuf>,
+
+    /// GitHub CLI executable to run instead of the one on PATH [env: BLAME_GH]
+    #[arg(long, value_name = "PATH")]
+    gh_bin: Option<PathBuf>,
+
+    /// Number of files to blame in parallel (defaults to the number of CPUs)
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// List each file under the contributor who owns most of it, instead of
+    /// the overall totals
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers"])]
+    per_file: bool,
+
+    /// Print GitHub CODEOWNERS lines giving each direct child of the directory
+    /// arguments to its top contributor (or --top N, above --threshold) as
+    /// @login, falling back to email for authors without a GitHub account
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers", "per_file"])]
+    codeowners: bool,
+
+    /// Show each directory with its top contributor (or --top N) as a tree;
+    /// directories with a single subdirectory and no files are collapsed
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers", "per_file", "codeowners"])]
+    tree: bool,
+
+    /// Also print the bus factor: how many top contributors together own more
+    /// than half of the code
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers"])]
+    bus_factor: bool,
+
+    /// Also print how concentrated ownership is, as a Gini coefficient from 0
+    /// (everyone owns the same share) to 1 (one person owns everything)
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers"])]
+    concentration: bool,
+
+    /// Also print how many of the blamed lines' commits were made in each
+    /// month (UTC), as a histogram: is the code actively maintained or dormant?
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name", "reviewers"])]
+    histogram: bool,
+
+    /// Don't count merge commits in commit totals (and --by-commits). Lines git
+    /// blames on a merge, such as conflict resolutions, still count for its author
+    #[arg(long)]
+    no_merges: bool,
+
+    /// Leave out files matching this glob (repeatable); same as a pattern with a
+    /// leading `!`, e.g. "!vendor/**"
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Blame files listed in the repository's .blameignore too
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Skip files that look binary, such as images, with a warning (the
+    /// default)
+    #[arg(long, overrides_with = "no_skip_binary")]
+    skip_binary: bool,
+
+    /// Blame files that look binary too
+    #[arg(long, overrides_with = "skip_binary")]
+    no_skip_binary: bool,
+
+    /// Skip files larger than this, with a warning (e.g. 500000, 200k, 5M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Only blame files at most N levels below each directory (1 = direct children)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+
+    /// In a shallow clone, fetch the full history before blaming (git fetch
+    /// --unshallow) so old lines aren't all credited to the oldest fetched commit
+    #[arg(long)]
+    unshallow: bool,
+
+    /// Count lines with uncommitted local edits under "(uncommitted)" instead of
+    /// leaving them out
+    #[arg(long)]
+    include_uncommitted: bool,
+
+    /// Also count files passed by name that exist but aren't tracked yet, with
+    /// all of their lines under "(uncommitted)"
+    #[arg(long, conflicts_with_all = ["rev", "until"])]
+    include_untracked: bool,
+
+    /// How to show last-touched dates: relative (the default), iso (2024-03-15),
+    /// short (Mar 2024), or a strftime format such as "%d/%m/%Y". Dates are in UTC.
+    /// With --json this replaces the RFC 3339 last_commit_date
+    #[arg(long, value_name = "FORMAT", value_parser = parse_date_format)]
+    date: Option<DateFormat>,
+
+    /// With -v, show each contributor's line count in its own column, and the
+    /// total underneath
+    #[arg(long)]
+    with_lines: bool,
+
+    /// With -v, start each contributor's line with a bar showing their share
+    #[arg(long)]
+    bars: bool,
+
+    /// With -v, start each contributor's line with a sparkline of the lines
+    /// they last touched in each of the past 12 months, oldest first
+    #[arg(long)]
+    sparkline: bool,
+
+    /// With -v, show each contributor's email addresses next to their name
+    #[arg(long)]
+    email: bool,
+
+    /// Count contributors by email instead of name, so one person committing
+    /// under several names (or two people sharing one) is told apart correctly
+    #[arg(long)]
+    merge_by_email: bool,
+
+    /// Count names that differ only in case or spacing ("John Doe", "john doe ")
+    /// as one contributor, shown as the spelling with the most lines
+    #[arg(long)]
+    normalize_names: bool,
+
+    /// With -v, also show the subject of each contributor's most recent commit
+    #[arg(long)]
+    show_message: bool,
+
+    /// With -v, also show when each contributor's oldest surviving line was
+    /// written ("since <date>")
+    #[arg(long)]
+    show_first: bool,
+
+    /// Only show the N top contributors
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    top: Option<u64>,
+
+    /// Only show contributors owning at least this percentage of lines (or
+    /// commits, with --by-commits)
+    #[arg(long, value_name = "PCT")]
+    threshold: Option<f64>,
+
+    /// Exit with status 8 if the top contributor owns less than this
+    /// percentage, i.e. nobody clearly owns the files (per file with --per-file)
+    #[arg(long, value_name = "PCT")]
+    fail_under: Option<f64>,
+
+    /// Exit with status 8 if the top contributor owns more than this
+    /// percentage, i.e. knowledge rests with one person (per file with --per-file)
+    #[arg(long, value_name = "PCT")]
+    fail_if_single_owner: Option<f64>,
+
+    /// Print top contributors as a comma-separated list of GitHub @mentions
+    /// (implies --gh, limited by --top, defaults to 3)
+    #[arg(long, conflicts_with_all = ["json", "csv", "markdown", "only_name"])]
+    reviewers: bool,
+
+    /// Exclude bot accounts such as dependabot and renovate (the default)
+    #[arg(long, overrides_with = "include_bots")]
+    no_bots: bool,
+
+    /// Include bot accounts in the results
+    #[arg(long, overrides_with = "no_bots")]
+    include_bots: bool,
+
+    /// Treat authors matching this glob as bots (e.g. "*-ci")
+    #[arg(long, value_name = "GLOB")]
+    bot_pattern: Vec<String>,
+
+    /// When to use colors in the output
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Plain text output with no escape codes (same as --color=never)
+    #[arg(long, conflicts_with = "color")]
+    plain: bool,
+
+    /// Write the results to this file instead of stdout; warnings and errors
+    /// still go to stderr, and colors are off unless --color=always
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
     /// Upgrade blame to the latest version
     #[arg(long)]
     upgrade: bool,
+
+    /// Check whether a newer release of blame is available
+    #[arg(long, conflicts_with = "upgrade")]
+    check_update: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    /// Most lines first
+    Lines,
+    /// Most recently touched first
+    Recent,
+    /// Most commits first
+    Commits,
+    /// Alphabetically by name
+    Name,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BackendChoice {
+    /// Shell out to `git blame`
+    Subprocess,
+    /// Blame in-process with libgit2
+    #[cfg(feature = "git2")]
+    Git2,
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// How last-touched dates are displayed.
+#[derive(Clone)]
+enum DateFormat {
+    Relative,
+    Strftime(String),
+}
+
+impl DateFormat {
+    fn format(&self, timestamp: i64) -> String {
+        match self {
+            DateFormat::Relative => format_relative_time(timestamp),
+            DateFormat::Strftime(format) => match Utc.timestamp_opt(timestamp, 0).single() {
+                Some(dt) if timestamp > 0 => dt.format(format).to_string(),
+                _ => "unknown".to_string(),
+            },
+        }
+    }
+}
+
+/// What an author's ownership percentage is a share of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    Lines,
+    WeightedLines,
+    /// Each file's lines, split by share, so every file weighs one
+    FileShares,
+    Commits,
+}
+
+impl Metric {
+    fn of(self, stats: &AuthorStats) -> f64 {
+        match self {
+            Metric::Lines => stats.lines() as f64,
+            Metric::WeightedLines | Metric::FileShares => stats.weighted_lines(),
+            Metric::Commits => stats.commits().len() as f64,
+        }
+    }
+
+    /// Suffix for displayed percentages, spelling out the metric when it isn't
+    /// the default.
+    fn share_label(self) -> &'static str {
+        match self {
+            Metric::Lines => "",
+            Metric::WeightedLines => " weighted",
+            Metric::FileShares => " per file",
+            Metric::Commits => " of commits",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Lines => "lines",
+            Metric::WeightedLines => "weighted_lines",
+            Metric::FileShares => "files",
+            Metric::Commits => "commits",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    author: &'a str,
+    lines: usize,
+    percentage: f64,
+    percentage_of: &'static str,
+    last_commit_time: i64,
+    /// `None` (null) for a timestamp chrono can't represent.
+    last_commit_date: Option<String>,
+    last_commit_sha: Option<&'a str>,
+    first_commit_time: i64,
+    commits: usize,
+    files: usize,
+}
+
+fn main() {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    load_config().apply(&mut args, &matches);
+    // `conflicts_with` can't single out one value of a positional argument
+    if args.stdin_diff
+        && (args.patterns.iter().any(|p| p == "-")
+            || args.files_from.as_deref() == Some("-")
+            || args.files_from0.as_deref() == Some("-"))
+    {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--stdin-diff' cannot be used with '-': both read from stdin",
+            )
+            .exit();
+    }
+
+    if let Some(program) = &args.git_bin {
+        set_git_program(program);
+    }
+    if let Some(program) = &args.gh_bin {
+        set_gh_program(program);
+    }
+
+    if args.upgrade {
+        upgrade();
+        return;
+    }
+
+    if args.check_update {
+        check_update();
+        return;
+    }
+
+    let mut options = BlameOptions {
+        since: args.since.clone(),
+        until: args.until.clone(),
+        ignore_whitespace: args.ignore_whitespace,
+        ignore_blank_lines: args.ignore_blank_lines,
+        ignore_comments: args.ignore_comments,
+        follow: args.follow,
+        first_parent: args.first_parent,
+        ignore_revs_file: args.ignore_revs_file.clone(),
+        // A diff's line numbers are of its base, not the working tree
+        rev: args
+            .rev
+            .clone()
+            .or_else(|| args.stdin_diff.then(|| "HEAD".to_string())),
+        function: args.function.clone(),
+        jobs: args.jobs,
+        decay: args.decay,
+        per_file: args.per_file || args.codeowners || args.tree,
+        per_file_weight: args.per_file_weight,
+        max_depth: args.max_depth.map(|n| n as usize),
+        exclude: args.exclude.clone(),
+        no_ignore: args.no_ignore,
+        include_binary: args.no_skip_binary,
+        max_file_size: args.max_file_size,
+        no_merges: args.no_merges,
+        include_uncommitted: args.include_uncommitted,
+        quiet: args.quiet,
+        include_untracked: args.include_untracked,
+        merge_by_email: args.merge_by_email,
+        normalize_names: args.normalize_names,
+        unshallow: args.unshallow,
+        committer: args.committer,
+        co_authors: args.co_authors,
+        timeout: (args.timeout > 0).then(|| Duration::from_secs(args.timeout)),
+        // Only for someone watching: not with --quiet or when piping results
+        progress: !args.quiet && std::io::stdout().is_terminal(),
+        backend: match args.backend {
+            BackendChoice::Subprocess => Backend::Subprocess,
+            #[cfg(feature = "git2")]
+            BackendChoice::Git2 => Backend::Git2,
+        },
+        dry_run: args.dry_run,
+        line_ranges: vec![],
+    };
+
+    // Created before blaming so a bad path fails fast
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => Box::new(LineWriter::new(file)),
+            Err(e) => {
+                eprintln!("Error: Could not write to '{}': {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let to_terminal = args.output.is_none() && std::io::stdout().is_terminal();
+    let color = !args.plain && use_color(args.color, to_terminal);
+
+    let mut patterns: Vec<String> = Vec::new();
+    for pattern in &args.patterns {
+        if pattern == "-" {
+            patterns.extend(read_path_list("-", '\n'));
+        } else {
+            patterns.push(pattern.clone());
+        }
+    }
+    if let Some(source) = &args.files_from {
+        patterns.extend(read_path_list(source, '\n'));
+    }
+    if let Some(source) = &args.files_from0 {
+        patterns.extend(read_path_list(source, '\0'));
+    }
+    if args.stdin_diff {
+        let mut diff = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut diff) {
+            eprintln!("Error: Could not read a diff from stdin: {}", e);
+            std::process::exit(1);
+        }
+        // Diffs name files relative to the top of the repository
+        let top = git_command()
+            .args(["rev-parse", "--show-toplevel"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+            .unwrap_or_default();
+        let ranges = diff_line_ranges(&diff);
+        if ranges.is_empty() && patterns.is_empty() {
+            if args.json {
+                writeln!(out, "[]").unwrap_or_else(write_failed);
+            } else if !args.quiet {
+                eprintln!("Nothing to blame: the diff doesn't change or remove any lines");
+            }
+            return;
+        }
+        options.line_ranges = ranges
+            .into_iter()
+            .map(|(path, start, end)| (top.join(path), start, end))
+            .collect();
+    }
+
+    let exclude_bots = args.no_bots || !args.include_bots;
+    let bot_patterns: Vec<Pattern> = args
+        .bot_pattern
+        .iter()
+        .map(|p| match Pattern::new(p) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                eprintln!("Error: Invalid bot pattern '{}': {}", p, e);
+                std::process::exit(2);
+            }
+        })
+        .collect();
+    let is_filtered_out = |author: &str| {
+        matches_author(author, &args.exclude_author)
+            || (exclude_bots && is_bot(author, &bot_patterns))
+    };
+    let metric = if args.by_commits {
+        Metric::Commits
+    } else if args.per_file_weight {
+        Metric::FileShares
+    } else if args.decay.is_some() || args.co_authors {
+        Metric::WeightedLines
+    } else {
+        Metric::Lines
+    };
+
+    if let Some((from, to)) = &args.compare {
+        let blame_at = |rev: &str| {
+            let options = BlameOptions {
+                rev: Some(rev.to_string()),
+                ..options.clone()
+            };
+            let report = blame_paths(&patterns, &options).unwrap_or_else(|e| {
+                eprintln!("Error: {}", e);
+                std::process::exit(exit_code(&e));
+            });
+            let mut stats = report.stats;
+            stats.retain(|author, _| !is_filtered_out(author));
+            (stats, report.files.len())
+        };
+        let (before, files_before) = blame_at(from);
+        let (after, files_after) = blame_at(to);
+        if files_before == 0 && files_after == 0 {
+            eprintln!("Error: No git-tracked files found at {} or {}", from, to);
+            std::process::exit(6);
+        }
+        print_compare(&mut out, &before, &after, (from, to), metric, color)
+            .unwrap_or_else(write_failed);
+        return;
+    }
+
+    let report = match blame_paths(&patterns, &options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(exit_code(&e));
+        }
+    };
+
+    if args.debug {
+        for (path, reason) in &report.skipped {
+            let path = report
+                .root
+                .as_ref()
+                .and_then(|root| Path::new(path).strip_prefix(root).ok())
+                .unwrap_or(Path::new(path));
+            eprintln!("debug: skipped {} ({})", path.display(), reason);
+        }
+    }
+
+    if report.files.is_empty() {
+        if args.json {
+            writeln!(out, "[]").unwrap_or_else(write_failed);
+            return;
+        }
+        eprintln!("Error: No git-tracked files found");
+        std::process::exit(6);
+    }
+
+    let root = report.root.unwrap();
+
+    if args.dry_run {
+        let mut files: Vec<String> = report
+            .files
+            .iter()
+            .map(|file| {
+                let path = Path::new(file);
+                path.strip_prefix(&root)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        files.sort();
+        for file in &files {
+            writeln!(out, "{}", file).unwrap_or_else(write_failed);
+        }
+        let count = pluralize(files.len(), "file");
+        writeln!(out, "{}", paint(&count, DIM, color)).unwrap_or_else(write_failed);
+        return;
+    }
+
+    if report.stats.is_empty() && !report.failed.is_empty() {
+        eprintln!("Error: No blame data found");
+        std::process::exit(1);
+    }
+
+    // Zero-byte files and repositories without commits blame to nothing, which
+    // isn't an error
+    if report.stats.is_empty() {
+        if args.json {
+            writeln!(out, "[]").unwrap_or_else(write_failed);
+        } else if !args.quiet {
+            eprintln!(
+                "Nothing to blame: the matched files have no committed lines yet {}",
+                format_count(report.files.len(), "file")
+            );
+        }
+        return;
+    }
+
+    let mut stats = report.stats;
+    stats.retain(|author, _| !is_filtered_out(author));
+    let mut file_stats = report.file_stats;
+    for stats in file_stats.values_mut() {
+        stats.retain(|author, _| !is_filtered_out(author));
+    }
+
+    // Resolve GitHub usernames if --gh flag is set
+    let github_repo = if args.gh || args.reviewers || args.codeowners {
+        let Some((remote, mut github_repo)) = find_github_remote(&root, args.remote.as_deref())
+        else {
+            match &args.remote {
+                Some(remote) => eprintln!("Error: Remote '{}' is not a GitHub repository", remote),
+                None => eprintln!("Error: Could not determine GitHub repository from remote"),
+            }
+            std::process::exit(7);
+        };
+        if let Some(host) = &args.gh_host {
+            github_repo.host = host.clone();
+        }
+        if args.debug {
+            eprintln!(
+                "debug: using remote '{}' ({}/{}/{})",
+                remote, github_repo.host, github_repo.owner, github_repo.repo
+            );
+        }
+        Some(github_repo)
+    } else {
+        None
+    };
+
+    let mut gh_logins: HashSet<String> = HashSet::new();
+    // The git names behind each login, so --author can match either
+    let mut git_names: HashMap<String, Vec<String>> = HashMap::new();
+    let stats = match &github_repo {
+        Some(repo) => {
+            let mut cache = if args.no_cache {
+                LoginCache::default()
+            } else {
+                LoginCache::load()
+            };
+            let logins = resolve_github_logins(&stats, repo, &mut cache).unwrap_or_else(|e| {
+                // Carry on with the logins that were found
+                if !args.quiet {
+                    eprintln!("Warning: {}", e);
+                }
+                e.logins
+            });
+            cache.save();
+            let mut stats = rename_authors(stats, &logins);
+            stats.retain(|login, _| !is_filtered_out(login));
+            for stats in file_stats.values_mut() {
+                *stats = rename_authors(std::mem::take(stats), &logins);
+                stats.retain(|login, _| !is_filtered_out(login));
+            }
+            for (name, login) in &logins {
+                git_names
+                    .entry(login.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+            gh_logins = logins.into_values().collect();
+            stats
+        }
+        None => stats,
+    };
+
+    if stats.is_empty() {
+        if args.json {
+            writeln!(out, "[]").unwrap_or_else(write_failed);
+            return;
+        }
+        eprintln!(
+            "Error: Every contributor was filtered out by --exclude-author or the bot filter"
+        );
+        std::process::exit(1);
+    }
+
+    let sort = args.sort.unwrap_or(match metric {
+        Metric::Lines | Metric::WeightedLines | Metric::FileShares => SortKey::Lines,
+        Metric::Commits => SortKey::Commits,
+    });
+
+    // Checked up front, but only reported once the results are out
+    let failed_checks: Vec<String> = if args.per_file {
+        let mut files: Vec<_> = file_stats.iter().collect();
+        files.sort_by_key(|(file, _)| *file);
+        files
+            .into_iter()
+            .flat_map(|(file, stats)| {
+                let path = Path::new(file)
+                    .strip_prefix(&root)
+                    .unwrap_or(Path::new(file));
+                check_ownership(stats, metric, &args, &format!("{}: ", path.display()))
+            })
+            .collect()
+    } else {
+        check_ownership(&stats, metric, &args, "")
+    };
+
+    if args.codeowners {
+        // Directory arguments own their direct children; files own themselves
+        let dirs: Vec<PathBuf> = patterns
+            .iter()
+            .filter_map(|p| Path::new(p).canonicalize().ok())
+            .filter(|p| p.is_dir())
+            .collect();
+        let rules = codeowners_rules(
+            &file_stats,
+            &dirs,
+            &root,
+            metric,
+            &gh_logins,
+            args.top.unwrap_or(1) as usize,
+            args.threshold,
+        );
+        print_codeowners(&mut out, &rules).unwrap_or_else(write_failed);
+        exit_if_checks_failed(&failed_checks);
+        return;
+    }
+
+    if args.tree {
+        print_tree(
+            &mut out,
+            &file_stats,
+            &root,
+            metric,
+            args.top.unwrap_or(1) as usize,
+            color,
+        )
+        .unwrap_or_else(write_failed);
+        exit_if_checks_failed(&failed_checks);
+        return;
+    }
+
+    if args.per_file {
+        print_per_file(&mut out, &file_stats, &root, metric, color).unwrap_or_else(write_failed);
+        exit_if_checks_failed(&failed_checks);
+        return;
+    }
+
+    let mut authors: Vec<_> = stats.into_iter().collect();
+    sort_authors(&mut authors, sort, args.reverse);
+
+    // Percentages are always relative to every contributor, even when the list
+    // is truncated below
+    let total: f64 = authors.iter().map(|(_, s)| metric.of(s)).sum();
+    if total <= 0.0 {
+        // e.g. --by-commits when every line is uncommitted
+        if args.json {
+            writeln!(out, "[]").unwrap_or_else(write_failed);
+        } else if !args.quiet {
+            eprintln!(
+                "Nothing to blame: no attributable {}",
+                metric.name().replace('_', " ")
+            );
+        }
+        exit_if_checks_failed(&failed_checks);
+        return;
+    }
+    let percentage = |s: &AuthorStats| share_of(metric.of(s), total);
+    // For the verbose footer, before --threshold and --top trim the list
+    let total_lines: usize = authors.iter().map(|(_, s)| s.lines()).sum();
+    let contributors = authors.len();
+    let latest_touch = authors
+        .iter()
+        .map(|(_, s)| s.last_commit_time())
+        .max()
+        .unwrap_or(0);
+
+    // Round for display so the full list adds up to exactly 100%
+    let shares: Vec<f64> = authors.iter().map(|(_, s)| percentage(s)).collect();
+    let displayed: HashMap<String, f64> = authors
+        .iter()
+        .map(|(author, _)| author.clone())
+        .zip(largest_remainder_round(&shares))
+        .collect();
+    let displayed_percentage = |author: &str| displayed[author];
+
+    // Computed before --threshold and --top trim the list
+    let bus_factor_authors: Vec<String> = if args.bus_factor {
+        bus_factor(&authors, metric)
+    } else {
+        vec![]
+    };
+    let concentration = gini_coefficient(&shares);
+    let activity = if args.histogram {
+        commits_per_month(&authors)
+    } else {
+        BTreeMap::new()
+    };
+
+    if let Some(threshold) = args.threshold {
+        authors.retain(|(_, s)| percentage(s) >= threshold);
+        if authors.is_empty() {
+            eprintln!(
+                "Error: No contributor owns at least {}% of the {}",
+                threshold,
+                metric.name().replace('_', " ")
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if args.reviewers {
+        let current_user = github_repo.as_ref().and_then(get_current_github_user);
+        let reviewers: Vec<String> = authors
+            .iter()
+            .map(|(login, _)| login)
+            .filter(|login| gh_logins.contains(*login) && Some(*login) != current_user.as_ref())
+            .take(args.top.unwrap_or(3) as usize)
+            .map(|login| format!("@{}", login))
+            .collect();
+        writeln!(out, "{}", reviewers.join(", ")).unwrap_or_else(write_failed);
+        exit_if_checks_failed(&failed_checks);
+        return;
+    }
+
+    // Requested authors with nothing in scope own 0%, which is an answer too
+    let mut absent_authors: Vec<&str> = Vec::new();
+    if !args.author.is_empty() {
+        let is_named = |author: &str, name: &String| {
+            let names = std::slice::from_ref(name);
+            matches_author(author, names)
+                || git_names
+                    .get(author)
+                    .is_some_and(|git| git.iter().any(|n| matches_author(n, names)))
+        };
+        authors.retain(|(author, _)| args.author.iter().any(|name| is_named(author, name)));
+        absent_authors = args
+            .author
+            .iter()
+            .filter(|name| !authors.iter().any(|(author, _)| is_named(author, name)))
+            .map(String::as_str)
+            .collect();
+    }
+    let print_absent = |out: &mut dyn Write| -> io::Result<()> {
+        for name in &absent_authors {
+            writeln!(
+                out,
+                "{}  {:>5.1}%{}",
+                paint(name, author_color(name), color),
+                0.0,
+                metric.share_label()
+            )?;
+        }
+        Ok(())
+    };
+
+    let date_format = args.date.clone().unwrap_or(DateFormat::Relative);
+    // With --gh, link "last touched" to the author's latest commit on GitHub
+    let commit_links = github_repo.as_ref().filter(|_| color && to_terminal);
+    let last_touched = |s: &AuthorStats| {
+        format!(
+            "(last touched {})",
+            date_format.format(s.last_commit_time())
+        )
+    };
+    let link_commit = |text: String, s: &AuthorStats| match (commit_links, s.last_commit_sha()) {
+        (Some(repo), Some(sha)) => hyperlink(&text, &repo.commit_url(sha)),
+        _ => text,
+    };
+    let list_all = args.verbose || args.top.is_some() || args.threshold.is_some();
+    match args.top {
+        Some(n) => authors.truncate(n as usize),
+        None if !args.verbose && args.author.is_empty() => authors.truncate(1),
+        None => {}
+    }
+
+    if args.json {
+        print_json(&mut out, &authors, metric, total, args.date.as_ref())
+            .unwrap_or_else(write_failed);
+    } else if args.csv {
+        print_csv(&mut out, &authors, metric, total).unwrap_or_else(write_failed);
+    } else if args.markdown {
+        print_markdown(
+            &mut out,
+            &authors,
+            metric,
+            &displayed_percentage,
+            &date_format,
+            &gh_logins,
+        )
+        .unwrap_or_else(write_failed);
+    } else if args.only_name {
+        let terminator = if args.null { '\0' } else { '\n' };
+        for (author, _) in &authors {
+            write!(out, "{}{}", author, terminator).unwrap_or_else(write_failed);
+        }
+    } else if list_all {
+        // Show the count for whichever metric the percentage isn't based on
+        let count_labels: Vec<String> = authors
+            .iter()
+            .map(|(_, s)| match metric {
+                Metric::Lines | Metric::WeightedLines | Metric::FileShares => {
+                    format_count(s.commits().len(), "commit")
+                }
+                Metric::Commits => format_count(s.lines(), "line"),
+            })
+            .collect();
+        // Across several files, also show how widely each person's lines spread
+        let count_labels: Vec<String> = if report.files.len() > 1 {
+            let width = count_labels.iter().map(|l| l.len()).max().unwrap_or(0);
+            count_labels
+                .iter()
+                .zip(&authors)
+                .map(|(label, (_, s))| {
+                    format!(
+                        "{:<width$}  {}",
+                        label,
+                        format_count(s.files().len(), "file")
+                    )
+                })
+                .collect()
+        } else {
+            count_labels
+        };
+        let count_width = count_labels.iter().map(|l| l.len()).max().unwrap_or(0);
+
+        let subjects = if args.show_message {
+            let shas: Vec<&str> = authors
+                .iter()
+                .filter_map(|(_, s)| s.last_commit_sha())
+                .collect();
+            commit_subjects(&root, &shas)
+        } else {
+            HashMap::new()
+        };
+        let line_width = to_terminal.then(terminal_width);
+        let recent_months = if args.sparkline {
+            months_ending(Utc::now().timestamp(), SPARKLINE_MONTHS)
+        } else {
+            vec![]
+        };
+
+        // With --with-lines, names are padded so the line counts line up
+        let email_lists: Vec<Option<String>> = authors
+            .iter()
+            .map(|(author, author_stats)| {
+                // --merge-by-email already spells out the email of ambiguous names
+                if !args.email || author_stats.emails().is_empty() || author.ends_with('>') {
+                    return None;
+                }
+                let mut emails: Vec<&str> =
+                    author_stats.emails().iter().map(String::as_str).collect();
+                emails.sort_unstable();
+                Some(format!("<{}>", emails.join(", ")))
+            })
+            .collect();
+        let name_len = |author: &str, emails: &Option<String>| {
+            author.chars().count() + emails.as_ref().map_or(0, |e| e.chars().count() + 1)
+        };
+        let name_width = if args.with_lines {
+            authors
+                .iter()
+                .zip(&email_lists)
+                .map(|((author, _), emails)| name_len(author, emails))
+                .max()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let lines_width = total_lines.to_string().len();
+
+        writeln!(out).unwrap_or_else(write_failed);
+        for (((author, author_stats), count_label), emails) in
+            authors.iter().zip(&count_labels).zip(&email_lists)
+        {
+            let share = format!(
+                "{:>5.1}%{}",
+                displayed_percentage(author),
+                metric.share_label()
+            );
+            let touched = last_touched(author_stats);
+            let since = if args.show_first {
+                format!(
+                    "  (since {})",
+                    date_format.format(author_stats.first_commit_time())
+                )
+            } else {
+                String::new()
+            };
+            let subject = author_stats
+                .last_commit_sha()
+                .and_then(|sha| subjects.get(sha))
+                .map(|subject| {
+                    // Whatever room the rest of the line leaves, or a fixed
+                    // cap when the output isn't a terminal
+                    let name_cols = name_len(author, emails).max(name_width)
+                        + if args.with_lines { lines_width + 8 } else { 0 }
+                        + if args.bars { BAR_WIDTH + 2 } else { 0 }
+                        + if args.sparkline {
+                            SPARKLINE_MONTHS + 2
+                        } else {
+                            0
+                        };
+                    let used =
+                        name_cols + share.len() + count_width + touched.len() + since.len() + 8;
+                    let room = line_width.map_or(MAX_SUBJECT_WIDTH, |w| w.saturating_sub(used));
+                    format!("  {}", truncate_text(subject, room.max(MIN_SUBJECT_WIDTH)))
+                })
+                .unwrap_or_default();
+            let details = format!(
+                "{:<width$}  {}{}",
+                count_label,
+                link_commit(touched, author_stats),
+                since,
+                width = count_width
+            );
+            let mut name = paint(author, author_color(author), color);
+            if let Some(emails) = emails {
+                name = format!("{} {}", name, paint(emails, DIM, color));
+            }
+            let padding = name_width.saturating_sub(name_len(author, emails));
+            name.push_str(&" ".repeat(padding));
+            if args.with_lines {
+                let lines = author_stats.lines();
+                name = format!(
+                    "{}  {:>width$} {}",
+                    name,
+                    lines,
+                    if lines == 1 { "line " } else { "lines" },
+                    width = lines_width
+                );
+            }
+            if args.bars {
+                write!(
+                    out,
+                    "{}  ",
+                    share_bar(author, percentage(author_stats), color)
+                )
+                .unwrap_or_else(write_failed);
+            }
+            if args.sparkline {
+                let line = sparkline(author_stats, &recent_months);
+                write!(out, "{}  ", paint(&line, author_color(author), color))
+                    .unwrap_or_else(write_failed);
+            }
+            writeln!(
+                out,
+                "{}  {}  {}{}",
+                name,
+                share,
+                paint(&details, DIM, color),
+                subject
+            )
+            .unwrap_or_else(write_failed);
+        }
+        print_absent(&mut out).unwrap_or_else(write_failed);
+        // With --with-lines the total sits under the line counts
+        let lines = if args.with_lines {
+            format!(
+                "{:<name_width$}  {:>lines_width$} {}",
+                "",
+                total_lines,
+                if total_lines == 1 { "line " } else { "lines" }
+            )
+        } else {
+            pluralize(total_lines, "line")
+        };
+        let footer = format!(
+            "{} by {} in {}, last touched {}",
+            lines,
+            pluralize(contributors, "contributor"),
+            pluralize(report.files.len(), "file"),
+            date_format.format(latest_touch)
+        );
+        writeln!(out, "{}", paint(&footer, DIM, color)).unwrap_or_else(write_failed);
+        writeln!(out).unwrap_or_else(write_failed);
+    } else {
+        for (author, author_stats) in &authors {
+            writeln!(
+                out,
+                "{}  {:>5.1}%{}  {}",
+                paint(author, author_color(author), color),
+                displayed_percentage(author),
+                metric.share_label(),
+                paint(
+                    &link_commit(last_touched(author_stats), author_stats),
+                    DIM,
+                    color
+                )
+            )
+            .unwrap_or_else(write_failed);
+        }
+        print_absent(&mut out).unwrap_or_else(write_failed);
+    }
+
+    if args.bus_factor {
+        writeln!(
+            out,
+            "Bus factor: {}  {}",
+            bus_factor_authors.len(),
+            paint(&format!("({})", bus_factor_authors.join(", ")), DIM, color)
+        )
+        .unwrap_or_else(write_failed);
+    }
+
+    if args.concentration {
+        writeln!(
+            out,
+            "Concentration: {:.2}  {}",
+            concentration,
+            paint(
+                &format!("({})", describe_concentration(concentration, shares.len())),
+                DIM,
+                color
+            )
+        )
+        .unwrap_or_else(write_failed);
+    }
+
+    if args.histogram {
+        print_histogram(&mut out, &activity, color).unwrap_or_else(write_failed);
+    }
+
+    if !args.json
+        && !args.csv
+        && !args.markdown
+        && !args.only_name
+        && !args.quiet
+        && std::io::stderr().is_terminal()
+    {
+        nudge_if_outdated();
+    }
+
+    exit_if_checks_failed(&failed_checks);
+}
+
+/// Report a failure to write the results and exit. A closed pipe (`blame -v |
+/// head`) isn't worth a message.
+fn write_failed<T>(error: io::Error) -> T {
+    if error.kind() != io::ErrorKind::BrokenPipe {
+        eprintln!("Error: Could not write output: {}", error);
+    }
+    std::process::exit(1);
+}
+
+/// Why the top contributor in `stats` fails --fail-under or
+/// --fail-if-single-owner, if they do. Each message starts with `prefix`.
+fn check_ownership(
+    stats: &HashMap<String, AuthorStats>,
+    metric: Metric,
+    args: &Args,
+    prefix: &str,
+) -> Vec<String> {
+    let Some((author, share)) = top_owner(stats, metric) else {
+        return vec![];
+    };
+    let metric_name = metric.name().replace('_', " ");
+    let mut failures = Vec::new();
+    if let Some(min) = args.fail_under.filter(|&min| share < min) {
+        failures.push(format!(
+            "{}{} owns only {:.1}% of the {}, under --fail-under {}%",
+            prefix, author, share, metric_name, min
+        ));
+    }
+    if let Some(max) = args.fail_if_single_owner.filter(|&max| share > max) {
+        failures.push(format!(
+            "{}{} owns {:.1}% of the {}, over --fail-if-single-owner {}%",
+            prefix, author, share, metric_name, max
+        ));
+    }
+    failures
+}
+
+/// Report each failed ownership check and exit with status 8, if there were
+/// any.
+fn exit_if_checks_failed(failures: &[String]) {
+    if failures.is_empty() {
+        return;
+    }
+    for failure in failures {
+        eprintln!("Error: {}", failure);
+    }
+    std::process::exit(8);
+}
+
+/// `part` as a percentage of `total`, or 0 when there is nothing to share out
+/// (rather than NaN or infinity).
+fn share_of(part: f64, total: f64) -> f64 {
+    if total > 0.0 {
+        part / total * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Round percentages to one decimal with the largest remainder (Hamilton)
+/// method, so they still add up to the same total as the unrounded values.
+/// Ties go to later entries.
+fn largest_remainder_round(percentages: &[f64]) -> Vec<f64> {
+    let tenths: Vec<f64> = percentages.iter().map(|p| p * 10.0).collect();
+    let mut rounded: Vec<f64> = tenths.iter().map(|t| t.floor()).collect();
+
+    let target = tenths.iter().sum::<f64>().round();
+    let shortfall = (target - rounded.iter().sum::<f64>()).max(0.0) as usize;
+
+    let mut by_remainder: Vec<usize> = (0..tenths.len()).collect();
+    by_remainder.sort_by(|&a, &b| {
+        let remainder = |i: usize| tenths[i] - rounded[i];
+        remainder(b).total_cmp(&remainder(a)).then(b.cmp(&a))
+    });
+    for &i in by_remainder.iter().take(shortfall) {
+        rounded[i] += 1.0;
+    }
+
+    rounded.iter().map(|t| t / 10.0).collect()
+}
+
+/// How many distinct commits behind the authors' lines were made in each
+/// (year, month), in UTC.
+fn commits_per_month(authors: &[(String, AuthorStats)]) -> BTreeMap<(i32, u32), usize> {
+    // A commit can credit several authors (co-authors, merged names)
+    let times: HashMap<&str, i64> = authors
+        .iter()
+        .flat_map(|(_, s)| s.commit_times())
+        .map(|(sha, time)| (sha.as_str(), *time))
+        .collect();
+    let mut months = BTreeMap::new();
+    for month in times.into_values().filter_map(month_of) {
+        *months.entry(month).or_default() += 1;
+    }
+    months
+}
+
+/// Print one bar per month from the first month with a commit to the last,
+/// quiet months included, scaled so the busiest month fills [`BAR_WIDTH`].
+fn print_histogram(
+    out: &mut dyn Write,
+    months: &BTreeMap<(i32, u32), usize>,
+    color: bool,
+) -> io::Result<()> {
+    let (Some(first), Some(last)) = (months.keys().next(), months.keys().next_back()) else {
+        return Ok(());
+    };
+    let busiest = months.values().max().copied().unwrap_or(0);
+    let count_width = busiest.to_string().len();
+
+    writeln!(out, "Commits per month:")?;
+    let (mut year, mut month) = *first;
+    while (year, month) <= *last {
+        let count = months.get(&(year, month)).copied().unwrap_or(0);
+        // Any activity at all gets at least a sliver
+        let width = match count {
+            0 => 0,
+            _ => (count * BAR_WIDTH).div_ceil(busiest),
+        };
+        writeln!(
+            out,
+            "  {}-{:02}  {:<bar_width$}  {}",
+            year,
+            month,
+            "█".repeat(width),
+            paint(&format!("{:>count_width$}", count), DIM, color),
+            bar_width = BAR_WIDTH
+        )?;
+        (year, month) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+    }
+    Ok(())
 }
 
-#[derive(Default)]
-struct AuthorStats {
-    lines: usize,
-    last_commit_time: i64,
-    commits: HashSet<String>,
-}
+/// The fewest top contributors who together own more than half of `metric`.
+fn bus_factor(authors: &[(String, AuthorStats)], metric: Metric) -> Vec<String> {
+    let mut by_share: Vec<_> = authors.iter().collect();
+    by_share.sort_by(|(a_name, a), (b_name, b)| {
+        metric
+            .of(b)
+            .total_cmp(&metric.of(a))
+            .then_with(|| a_name.cmp(b_name))
+    });
 
-fn main() {
-    let args = Args::parse();
+    let total: f64 = authors.iter().map(|(_, s)| metric.of(s)).sum();
+    let mut owned = 0.0;
+    let mut names = Vec::new();
+    for (author, author_stats) in by_share {
+        names.push(author.clone());
+        owned += metric.of(author_stats);
+        if owned * 2.0 > total {
+            break;
+        }
+    }
+    names
+}
 
-    if args.upgrade {
-        upgrade();
-        return;
+/// Gini coefficient of `shares`: 0 when they are all equal, 1 when one of them
+/// takes everything. It's scaled by n/(n-1) so that holds for few contributors
+/// too; a lone share counts as fully concentrated.
+fn gini_coefficient(shares: &[f64]) -> f64 {
+    let total: f64 = shares.iter().sum();
+    if shares.len() < 2 || total <= 0.0 {
+        return if shares.len() == 1 { 1.0 } else { 0.0 };
     }
+    let mut sorted = shares.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    let weighted: f64 = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, share)| (i + 1) as f64 * share)
+        .sum();
+    let gini = 2.0 * weighted / (n * total) - (n + 1.0) / n;
+    (gini * n / (n - 1.0)).clamp(0.0, 1.0)
+}
 
-    // Expand all patterns and collect unique files
-    let mut all_files: HashSet<String> = HashSet::new();
-    let mut git_root: Option<PathBuf> = None;
+/// A few words on what a Gini coefficient from [`gini_coefficient`] means for
+/// `contributors` people.
+fn describe_concentration(gini: f64, contributors: usize) -> &'static str {
+    match gini {
+        _ if contributors == 1 => "a single contributor",
+        g if g < 0.25 => "evenly spread",
+        g if g < 0.5 => "somewhat concentrated",
+        g if g < 0.75 => "concentrated",
+        _ => "dominated by a few contributors",
+    }
+}
 
-    for pattern in &args.patterns {
-        let expanded = expand_pattern(pattern);
+/// Read a `separator`-delimited list of paths from `source`, a file or `-` for
+/// stdin. Blank entries are skipped.
+fn read_path_list(source: &str, separator: char) -> Vec<String> {
+    let contents = if source == "-" {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .map(|_| contents)
+    } else {
+        std::fs::read_to_string(source)
+    };
 
-        if expanded.is_empty() {
-            eprintln!("Warning: No files matched '{}'", pattern);
-            continue;
+    match contents {
+        Ok(contents) => contents
+            .split(separator)
+            .map(|path| path.trim_end_matches('\r'))
+            .filter(|path| !path.trim().is_empty())
+            .map(String::from)
+            .collect(),
+        Err(e) => {
+            eprintln!("Error: Could not read paths from '{}': {}", source, e);
+            std::process::exit(1);
         }
+    }
+}
 
-        for path in expanded {
-            // Get git root from first valid path
-            if git_root.is_none() {
-                git_root = get_git_root(&path);
-            }
+/// The lines of each file a unified diff changes or removes, as (path, first
+/// line, last line) in the file before the change. Context lines, new files,
+/// and added lines contribute nothing.
+fn diff_line_ranges(diff: &str) -> Vec<(String, usize, usize)> {
+    // "12,3" in a hunk header, where a missing count means 1
+    let span = |span: &str| -> Option<(usize, usize)> {
+        let (start, count) = span.split_once(',').unwrap_or((span, "1"));
+        Some((start.parse().ok()?, count.parse().ok()?))
+    };
 
-            let root = match &git_root {
-                Some(r) => r,
-                None => {
-                    eprintln!("Error: '{}' is not in a git repository", path.display());
-                    std::process::exit(1);
+    let mut ranges: Vec<(String, usize, usize)> = Vec::new();
+    let mut path: Option<String> = None;
+    // The old file's next line number, and how many lines of the current hunk
+    // are still to come on each side (saturating, should a hunk be malformed)
+    let (mut old_line, mut old_left, mut new_left): (usize, usize, usize) = (0, 0, 0);
+    for line in diff.lines() {
+        if old_left > 0 || new_left > 0 {
+            match line.chars().next() {
+                Some('-') => {
+                    if let Some(path) = &path {
+                        match ranges.last_mut() {
+                            Some((last, _, end)) if last == path && *end + 1 == old_line => {
+                                *end = old_line
+                            }
+                            _ => ranges.push((path.clone(), old_line, old_line)),
+                        }
+                    }
+                    old_line += 1;
+                    old_left = old_left.saturating_sub(1);
                 }
-            };
-
-            if path.is_dir() {
-                for f in get_git_files_in_dir(&path, root) {
-                    all_files.insert(f);
+                Some('+') => new_left = new_left.saturating_sub(1),
+                Some('\\') => {}
+                // Context, possibly with its leading space stripped by a mailer
+                _ => {
+                    old_line += 1;
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
                 }
-            } else if is_git_tracked(&path, root) {
-                all_files.insert(path.to_string_lossy().to_string());
+            }
+            continue;
+        }
+
+        if let Some(old) = line.strip_prefix("--- ") {
+            // git adds a tab after names with spaces; GNU diff adds a date
+            let old = old.split('\t').next().unwrap_or(old).trim_end();
+            let old = old.trim_matches('"');
+            path = (old != "/dev/null").then(|| old.strip_prefix("a/").unwrap_or(old).to_string());
+        } else if let Some(header) = line.strip_prefix("@@ -") {
+            let mut sides = header.split_whitespace();
+            let old = sides.next().and_then(span);
+            let new = sides
+                .next()
+                .and_then(|s| s.strip_prefix('+'))
+                .and_then(span);
+            if let (Some((start, old_count)), Some((_, new_count))) = (old, new) {
+                (old_line, old_left, new_left) = (start, old_count, new_count);
             }
         }
     }
+    ranges
+}
 
-    let files: Vec<String> = all_files.into_iter().collect();
+/// Defaults for command-line flags read from config files. Every key is
+/// optional; unknown keys are an error so typos don't go unnoticed.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    gh: Option<bool>,
+    sort: Option<SortKey>,
+    no_bots: Option<bool>,
+    color: Option<ColorChoice>,
+    top: Option<u64>,
+    bot_pattern: Option<Vec<String>>,
+}
 
-    if files.is_empty() {
-        eprintln!("Error: No git-tracked files found");
-        std::process::exit(1);
+impl Config {
+    /// `self` with every key `other` sets replaced.
+    fn overlay(self, other: Config) -> Config {
+        Config {
+            gh: other.gh.or(self.gh),
+            sort: other.sort.or(self.sort),
+            no_bots: other.no_bots.or(self.no_bots),
+            color: other.color.or(self.color),
+            top: other.top.or(self.top),
+            bot_pattern: other.bot_pattern.or(self.bot_pattern),
+        }
     }
 
-    let git_root = git_root.unwrap();
-
-    let mut stats: HashMap<String, AuthorStats> = HashMap::new();
+    /// Fill in `args` from the config wherever the flag wasn't given on the
+    /// command line.
+    fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
 
-    for file in &files {
-        if let Err(e) = collect_blame_stats(file, &git_root, &mut stats) {
-            eprintln!("Warning: Could not process '{}': {}", file, e);
+        if let Some(gh) = self.gh.filter(|_| !from_cli("gh")) {
+            args.gh = gh;
+        }
+        if let Some(sort) = self.sort.filter(|_| !from_cli("sort")) {
+            args.sort = Some(sort);
+        }
+        if let Some(no_bots) = self
+            .no_bots
+            .filter(|_| !from_cli("no_bots") && !from_cli("include_bots"))
+        {
+            args.no_bots = no_bots;
+            args.include_bots = !no_bots;
+        }
+        if let Some(color) = self.color.filter(|_| !from_cli("color")) {
+            args.color = color;
+        }
+        if let Some(top) = self.top.filter(|_| !from_cli("top")) {
+            args.top = Some(top);
+        }
+        if let Some(patterns) = self.bot_pattern.filter(|_| !from_cli("bot_pattern")) {
+            args.bot_pattern = patterns;
         }
     }
+}
 
-    if stats.is_empty() {
-        eprintln!("Error: No blame data found");
-        std::process::exit(1);
+/// The user config overlaid with the repository's `.blame.toml`, if either
+/// exists. An unreadable or invalid file is a usage error.
+fn load_config() -> Config {
+    let user_config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .map(|dir| dir.join("blame").join("config.toml"));
+    // The repository root is the nearest directory with a .git or .hg entry
+    let repo_config = std::env::current_dir().ok().and_then(|cwd| {
+        cwd.ancestors()
+            .find(|dir| dir.join(".git").exists() || dir.join(".hg").is_dir())
+            .map(|root| root.join(".blame.toml"))
+    });
+
+    [user_config, repo_config]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| toml::from_str::<Config>(&text).map_err(|e| e.to_string()))
+                .and_then(|config| match config.top {
+                    Some(0) => Err("top must be at least 1".to_string()),
+                    _ => Ok(config),
+                });
+            parsed.unwrap_or_else(|e| {
+                eprintln!("Error: Invalid config file '{}': {}", path.display(), e);
+                std::process::exit(2);
+            })
+        })
+        .fold(Config::default(), Config::overlay)
+}
+
+/// Exit status for each failure class, as listed under EXIT STATUS in `--help`.
+fn exit_code(error: &BlameError) -> i32 {
+    match error {
+        BlameError::GitNotFound | BlameError::HgNotFound => 127,
+        BlameError::NotARepository(_) => 3,
+        BlameError::UnsupportedByBackend { .. } | BlameError::InvalidExcludePattern(_) => 2,
+        BlameError::UnknownRevision(_) | BlameError::NoCommitsBefore(_) => 4,
+        BlameError::BlameFailed { .. }
+        | BlameError::FileRemoved
+        | BlameError::TimedOut(_)
+        | BlameError::ParseError(_) => 5,
+        BlameError::IgnoreRevsFileNotFound(_)
+        | BlameError::FunctionNeedsSingleFile
+        | BlameError::UnshallowFailed { .. }
+        | BlameError::Io(_) => 1,
     }
+}
 
-    // Resolve GitHub usernames if --gh flag is set
-    let stats = if args.gh {
-        match get_github_repo(&git_root) {
-            Some((owner, repo)) => resolve_github_usernames(stats, &owner, &repo),
-            None => {
-                eprintln!("Error: Could not determine GitHub repository from remote");
-                std::process::exit(1);
-            }
+/// 256-color codes readable on both dark and light backgrounds, for
+/// [`author_color`].
+const AUTHOR_COLORS: [&str; 12] = [
+    "38;5;208", "38;5;33", "38;5;35", "38;5;170", "38;5;166", "38;5;37", "38;5;134", "38;5;172",
+    "38;5;69", "38;5;71", "38;5;161", "38;5;130",
+];
+
+/// A color for `author` that stays the same across runs, picked by an FNV-1a
+/// hash of the name (std's hasher isn't guaranteed stable between releases).
+fn author_color(author: &str) -> &'static str {
+    let hash = author.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    AUTHOR_COLORS[(hash % AUTHOR_COLORS.len() as u64) as usize]
+}
+const DIM: &str = "2";
+/// Green and red, for shares that grew or shrank under --compare.
+const GAIN: &str = "32";
+const LOSS: &str = "31";
+
+/// Decide whether to emit ANSI colors: `auto` honors `NO_COLOR` and only colors
+/// when stdout is a terminal.
+fn use_color(choice: ColorChoice, to_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && to_terminal
         }
+    }
+}
+
+fn paint(text: &str, code: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
     } else {
-        stats
-    };
+        text.to_string()
+    }
+}
 
-    let mut authors: Vec<_> = stats.into_iter().collect();
-    authors.sort_by(|a, b| b.1.lines.cmp(&a.1.lines));
+/// Widest a --show-message subject gets when the output isn't a terminal.
+const MAX_SUBJECT_WIDTH: usize = 72;
 
-    let total_lines: usize = authors.iter().map(|(_, s)| s.lines).sum();
+/// Narrowest a --show-message subject gets, however little room is left.
+const MIN_SUBJECT_WIDTH: usize = 20;
 
-    if args.only_name {
-        if args.verbose {
-            for (author, _) in &authors {
-                println!("{}", author);
-            }
+/// Width of the terminal, from `COLUMNS` or `stty size`, defaulting to 80.
+fn terminal_width() -> usize {
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return columns;
+    }
+    std::fs::File::open("/dev/tty")
+        .ok()
+        .and_then(|tty| Command::new("stty").arg("size").stdin(tty).output().ok())
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            // "<rows> <columns>"
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|columns| columns.parse().ok())
+        })
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+}
+
+/// Cut `text` to at most `width` characters, marking the cut with an ellipsis.
+fn truncate_text(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(width.saturating_sub(1)).collect();
+    format!("{}…", kept)
+}
+
+/// How many months a --sparkline covers, one character each.
+const SPARKLINE_MONTHS: usize = 12;
+
+/// The `count` months up to and including the one `time` falls in, oldest
+/// first.
+fn months_ending(time: i64, count: usize) -> Vec<(i32, u32)> {
+    let Some((mut year, mut month)) = month_of(time) else {
+        return vec![];
+    };
+    let mut months = Vec::with_capacity(count);
+    for _ in 0..count {
+        months.push((year, month));
+        (year, month) = if month == 1 {
+            (year - 1, 12)
         } else {
-            println!("{}", authors[0].0);
+            (year, month - 1)
+        };
+    }
+    months.reverse();
+    months
+}
+
+/// One block character per month in `months`, as tall as the author's lines
+/// from that month relative to their busiest one; blank for none at all.
+fn sparkline(stats: &AuthorStats, months: &[(i32, u32)]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let lines: Vec<usize> = months
+        .iter()
+        .map(|month| stats.monthly_lines().get(month).copied().unwrap_or(0))
+        .collect();
+    let busiest = lines.iter().max().copied().unwrap_or(0);
+    lines
+        .into_iter()
+        .map(|n| match n {
+            0 => ' ',
+            _ => BLOCKS[(n * BLOCKS.len()).div_ceil(busiest) - 1],
+        })
+        .collect()
+}
+
+/// Width of a --bars bar in characters.
+const BAR_WIDTH: usize = 20;
+
+/// A bar `percentage`% full: block characters in `author`'s color, or `#` and
+/// `-` without color.
+fn share_bar(author: &str, percentage: f64, color: bool) -> String {
+    let filled = ((percentage / 100.0 * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+    let empty = BAR_WIDTH - filled;
+    if color {
+        format!(
+            "{}{}",
+            paint(&"█".repeat(filled), author_color(author), color),
+            paint(&"░".repeat(empty), DIM, color)
+        )
+    } else {
+        format!("{}{}", "#".repeat(filled), "-".repeat(empty))
+    }
+}
+
+/// Wrap `text` in an OSC 8 escape so terminals that support it make it a link
+/// to `url`.
+fn hyperlink(text: &str, url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// The contributor with the largest share of `metric` in `stats`, and that
+/// share as a percentage. Ties go to the alphabetically first name.
+fn top_owner(stats: &HashMap<String, AuthorStats>, metric: Metric) -> Option<(&str, f64)> {
+    let total: f64 = stats.values().map(|s| metric.of(s)).sum();
+    let (author, author_stats) = stats.iter().max_by(|(a_name, a), (b_name, b)| {
+        metric
+            .of(a)
+            .total_cmp(&metric.of(b))
+            .then_with(|| b_name.cmp(a_name))
+    })?;
+    (total > 0.0).then(|| (author.as_str(), share_of(metric.of(author_stats), total)))
+}
+
+/// Group files under the author owning the largest share of each, listing
+/// authors with the most files first.
+fn print_per_file(
+    out: &mut dyn Write,
+    file_stats: &HashMap<String, HashMap<String, AuthorStats>>,
+    root: &Path,
+    metric: Metric,
+    color: bool,
+) -> io::Result<()> {
+    let mut owned: HashMap<&str, Vec<(String, f64)>> = HashMap::new();
+    for (file, stats) in file_stats {
+        let Some((author, share)) = top_owner(stats, metric) else {
+            continue;
+        };
+        let path = Path::new(file)
+            .strip_prefix(root)
+            .unwrap_or(Path::new(file));
+        owned
+            .entry(author)
+            .or_default()
+            .push((path.display().to_string(), share));
+    }
+
+    let mut owners: Vec<_> = owned.into_iter().collect();
+    owners
+        .sort_by(|(a_name, a), (b_name, b)| b.len().cmp(&a.len()).then_with(|| a_name.cmp(b_name)));
+    let path_width = owners
+        .iter()
+        .flat_map(|(_, files)| files)
+        .map(|(path, _)| path.len())
+        .max()
+        .unwrap_or(0);
+
+    writeln!(out)?;
+    for (author, mut files) in owners {
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        writeln!(
+            out,
+            "{}  {}",
+            paint(author, author_color(author), color),
+            paint(&format_count(files.len(), "file"), DIM, color)
+        )?;
+        for (path, percentage) in files {
+            writeln!(
+                out,
+                "  {:<width$}  {:>5.1}%{}",
+                path,
+                percentage,
+                metric.share_label(),
+                width = path_width
+            )?;
         }
-    } else if args.verbose {
-        println!();
-        for (author, author_stats) in &authors {
-            let percentage = (author_stats.lines as f64 / total_lines as f64) * 100.0;
-            let last_touch = format_relative_time(author_stats.last_commit_time);
-            println!(
-                "\x1b[38;5;208m{}\x1b[0m  {:>5.1}%  \x1b[2m(last touched {})\x1b[0m",
-                author, percentage, last_touch
-            );
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Print each contributor's share at two revisions and the change between
+/// them, biggest gain first. Contributors with nothing at the first revision
+/// are marked new, and those with nothing at the second departed.
+fn print_compare(
+    out: &mut dyn Write,
+    before: &HashMap<String, AuthorStats>,
+    after: &HashMap<String, AuthorStats>,
+    (from, to): (&str, &str),
+    metric: Metric,
+    color: bool,
+) -> io::Result<()> {
+    let total = |stats: &HashMap<String, AuthorStats>| stats.values().map(|s| metric.of(s)).sum();
+    let (before_total, after_total): (f64, f64) = (total(before), total(after));
+    let lines = |stats: &HashMap<String, AuthorStats>| stats.values().map(|s| s.lines()).sum();
+    let (before_lines, after_lines): (usize, usize) = (lines(before), lines(after));
+
+    // (author, share before, share after, change in lines)
+    let mut authors: Vec<&String> = before.keys().chain(after.keys()).collect();
+    authors.sort();
+    authors.dedup();
+    let mut rows: Vec<(&str, Option<f64>, Option<f64>, i64)> = authors
+        .into_iter()
+        .map(|author| {
+            let was = before.get(author);
+            let is = after.get(author);
+            let lines_of = |s: Option<&AuthorStats>| s.map_or(0, |s| s.lines() as i64);
+            (
+                author.as_str(),
+                was.map(|s| share_of(metric.of(s), before_total)),
+                is.map(|s| share_of(metric.of(s), after_total)),
+                lines_of(is) - lines_of(was),
+            )
+        })
+        .collect();
+    let change = |was: Option<f64>, is: Option<f64>| is.unwrap_or(0.0) - was.unwrap_or(0.0);
+    rows.sort_by(|(_, a_was, a_is, _), (_, b_was, b_is, _)| {
+        change(*b_was, *b_is).total_cmp(&change(*a_was, *a_is))
+    });
+    let name_width = rows
+        .iter()
+        .map(|(author, ..)| author.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    writeln!(out)?;
+    let heading = format!(
+        "Share of {} from {} to {}",
+        metric.name().replace('_', " "),
+        from,
+        to
+    );
+    writeln!(out, "{}", paint(&heading, DIM, color))?;
+    let lines_label = |lines: i64| match lines {
+        0 => "0 lines".to_string(),
+        1 | -1 => format!("{:+} line", lines),
+        _ => format!("{:+} lines", lines),
+    };
+    let lines_width = rows
+        .iter()
+        .map(|(.., lines)| lines_label(*lines).len())
+        .max()
+        .unwrap_or(0);
+    for (author, was, is, lines_changed) in rows {
+        let share = |share: Option<f64>| share.map_or("-".to_string(), |p| format!("{:.1}%", p));
+        let points = change(was, is);
+        let (points, points_color) = if points.abs() < 0.05 {
+            (" 0.0 pts".to_string(), DIM)
+        } else if points > 0.0 {
+            (format!("{:+.1} pts", points), GAIN)
+        } else {
+            (format!("{:+.1} pts", points), LOSS)
+        };
+        let status = match (was, is) {
+            (None, Some(_)) => format!("  {}", paint("new", GAIN, color)),
+            (Some(_), None) => format!("  {}", paint("departed", LOSS, color)),
+            _ => String::new(),
+        };
+        // Only padded to line up a status after it
+        let lines_width = if status.is_empty() { 0 } else { lines_width };
+        writeln!(
+            out,
+            "{}{}  {:>6} → {:<6}  {}  {}{}",
+            paint(author, author_color(author), color),
+            " ".repeat(name_width - author.chars().count()),
+            share(was),
+            share(is),
+            paint(&format!("{:>10}", points), points_color, color),
+            paint(
+                &format!("{:<lines_width$}", lines_label(lines_changed)),
+                DIM,
+                color
+            ),
+            status
+        )?;
+    }
+    let footer = format!(
+        "{} → {}, by {} → {}",
+        pluralize(before_lines, "line"),
+        pluralize(after_lines, "line"),
+        pluralize(before.len(), "contributor"),
+        pluralize(after.len(), "contributor")
+    );
+    writeln!(out, "{}", paint(&footer, DIM, color))?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// CODEOWNERS rules, as (path, owners), for every file's group: the direct
+/// child of the deepest directory in `dirs` containing it, or the file itself.
+fn codeowners_rules(
+    file_stats: &HashMap<String, HashMap<String, AuthorStats>>,
+    dirs: &[PathBuf],
+    root: &Path,
+    metric: Metric,
+    logins: &HashSet<String>,
+    top: usize,
+    threshold: Option<f64>,
+) -> Vec<(String, Vec<String>)> {
+    let mut groups: HashMap<PathBuf, HashMap<&str, AuthorStats>> = HashMap::new();
+    for (file, stats) in file_stats {
+        let file = Path::new(file);
+        let group = dirs
+            .iter()
+            .filter_map(|dir| Some((dir, file.strip_prefix(dir).ok()?)))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .and_then(|(dir, rest)| Some(dir.join(rest.components().next()?)))
+            .unwrap_or_else(|| file.to_path_buf());
+        let group_stats = groups.entry(group).or_default();
+        for (author, author_stats) in stats {
+            group_stats
+                .entry(author)
+                .or_default()
+                .merge(author_stats.clone());
         }
-        println!();
-    } else {
-        let (author, author_stats) = &authors[0];
-        let percentage = (author_stats.lines as f64 / total_lines as f64) * 100.0;
-        let last_touch = format_relative_time(author_stats.last_commit_time);
-        println!(
-            "\x1b[38;5;208m{}\x1b[0m  {:>5.1}%  \x1b[2m(last touched {})\x1b[0m",
-            author, percentage, last_touch
-        );
     }
+
+    let mut rules: Vec<(String, Vec<String>)> = groups
+        .into_iter()
+        .filter_map(|(group, stats)| {
+            let total: f64 = stats.values().map(|s| metric.of(s)).sum();
+            if total <= 0.0 {
+                return None;
+            }
+            let mut authors: Vec<_> = stats.into_iter().collect();
+            authors.sort_by(|(a_name, a), (b_name, b)| {
+                metric
+                    .of(b)
+                    .total_cmp(&metric.of(a))
+                    .then_with(|| a_name.cmp(b_name))
+            });
+            let owners: Vec<String> = authors
+                .iter()
+                .filter(|(_, s)| threshold.is_none_or(|t| share_of(metric.of(s), total) >= t))
+                .filter_map(|(author, s)| {
+                    if logins.contains(*author) {
+                        Some(format!("@{}", author))
+                    } else {
+                        s.emails().iter().min().cloned()
+                    }
+                })
+                .take(top)
+                .collect();
+            if owners.is_empty() {
+                return None;
+            }
+
+            let relative = group.strip_prefix(root).unwrap_or(&group);
+            let mut path = format!("/{}", relative.to_string_lossy());
+            if group.is_dir() {
+                path.push('/');
+            }
+            Some((path, owners))
+        })
+        .collect();
+    rules.sort();
+    rules
 }
 
-fn is_git_tracked(path: &Path, git_root: &Path) -> bool {
-    let relative = path.strip_prefix(git_root).unwrap_or(path);
-    let output = Command::new("git")
-        .args(["ls-files", "--error-unmatch", &relative.to_string_lossy()])
-        .current_dir(git_root)
-        .output();
+fn print_codeowners(out: &mut dyn Write, rules: &[(String, Vec<String>)]) -> io::Result<()> {
+    writeln!(
+        out,
+        "# Generated by `blame --codeowners` from line ownership"
+    )?;
+    for (path, owners) in rules {
+        // CODEOWNERS patterns escape spaces with a backslash
+        writeln!(out, "{} {}", path.replace(' ', "\\ "), owners.join(" "))?;
+    }
+    Ok(())
+}
 
-    matches!(output, Ok(o) if o.status.success())
+/// A directory in the `--tree` view, with stats for everything beneath it.
+#[derive(Default)]
+struct DirNode {
+    stats: HashMap<String, AuthorStats>,
+    /// Files directly in this directory.
+    files: usize,
+    children: BTreeMap<String, DirNode>,
 }
 
-fn expand_pattern(pattern: &str) -> Vec<PathBuf> {
-    // First try as a literal path
-    let literal_path = Path::new(pattern);
-    if literal_path.exists() {
-        return vec![literal_path.canonicalize().unwrap_or(literal_path.to_path_buf())];
+impl DirNode {
+    fn add(&mut self, stats: &HashMap<String, AuthorStats>) {
+        for (author, author_stats) in stats {
+            self.stats
+                .entry(author.clone())
+                .or_default()
+                .merge(author_stats.clone());
+        }
     }
 
-    // Try as a glob pattern
-    match glob(pattern) {
-        Ok(paths) => paths
-            .filter_map(|p| p.ok())
-            .filter_map(|p| p.canonicalize().ok())
-            .collect(),
-        Err(_) => vec![],
+    /// Follow a chain of directories that only contain one subdirectory,
+    /// returning the joined name and the last directory in the chain.
+    fn collapse<'a>(&'a self, name: &str) -> (String, &'a DirNode) {
+        let mut name = name.to_string();
+        let mut node = self;
+        while node.files == 0 && node.children.len() == 1 {
+            let (child_name, child) = node.children.iter().next().unwrap();
+            name = if name.is_empty() {
+                child_name.clone()
+            } else {
+                format!("{}/{}", name, child_name)
+            };
+            node = child;
+        }
+        (name, node)
     }
 }
 
-fn get_git_root(path: &Path) -> Option<PathBuf> {
-    let start_dir = if path.is_dir() { path } else { path.parent()? };
+/// Print the directories containing the blamed files as a tree, each with its
+/// `top` largest contributors.
+fn print_tree(
+    out: &mut dyn Write,
+    file_stats: &HashMap<String, HashMap<String, AuthorStats>>,
+    root: &Path,
+    metric: Metric,
+    top: usize,
+    color: bool,
+) -> io::Result<()> {
+    let mut tree = DirNode::default();
+    for (file, stats) in file_stats {
+        let relative = Path::new(file)
+            .strip_prefix(root)
+            .unwrap_or(Path::new(file));
+        let mut node = &mut tree;
+        node.add(stats);
+        if let Some(parent) = relative.parent() {
+            for component in parent.components() {
+                let name = component.as_os_str().to_string_lossy().to_string();
+                node = node.children.entry(name).or_default();
+                node.add(stats);
+            }
+        }
+        node.files += 1;
+    }
 
-    let output = Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .current_dir(start_dir)
-        .output()
-        .ok()?;
+    let owners = |node: &DirNode| -> String {
+        let total: f64 = node.stats.values().map(|s| metric.of(s)).sum();
+        let mut authors: Vec<_> = node.stats.iter().collect();
+        authors.sort_by(|(a_name, a), (b_name, b)| {
+            metric
+                .of(b)
+                .total_cmp(&metric.of(a))
+                .then_with(|| a_name.cmp(b_name))
+        });
+        authors
+            .iter()
+            .take(top)
+            .filter(|_| total > 0.0)
+            .map(|(author, s)| {
+                format!(
+                    "{} {:.1}%",
+                    paint(author, author_color(author), color),
+                    share_of(metric.of(s), total)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
 
-    if !output.status.success() {
-        return None;
+    fn print_children(
+        out: &mut dyn Write,
+        node: &DirNode,
+        prefix: &str,
+        owners: &dyn Fn(&DirNode) -> String,
+        color: bool,
+    ) -> io::Result<()> {
+        let count = node.children.len();
+        for (i, (name, child)) in node.children.iter().enumerate() {
+            let last = i + 1 == count;
+            let (name, child) = child.collapse(name);
+            writeln!(
+                out,
+                "{}{}{}/  {}",
+                paint(prefix, DIM, color),
+                paint(if last { "└── " } else { "├── " }, DIM, color),
+                name,
+                owners(child)
+            )?;
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            print_children(out, child, &prefix, owners, color)?;
+        }
+        Ok(())
     }
 
-    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    Some(PathBuf::from(root))
+    let (name, top_node) = tree.collapse("");
+    let name = if name.is_empty() { "." } else { &name };
+    writeln!(out, "{}/  {}", name, owners(top_node))?;
+    print_children(out, top_node, "", &owners, color)
 }
 
-fn get_git_files_in_dir(dir: &Path, git_root: &Path) -> Vec<String> {
-    let relative_dir = dir.strip_prefix(git_root).unwrap_or(dir);
+fn format_count(count: usize, noun: &str) -> String {
+    format!("({})", pluralize(count, noun))
+}
 
-    let output = Command::new("git")
-        .args(["ls-files", &relative_dir.to_string_lossy()])
-        .current_dir(git_root)
-        .output()
-        .expect("Failed to run git ls-files");
+fn pluralize(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
 
-    if !output.status.success() {
-        return vec![];
+fn sort_authors(authors: &mut [(String, AuthorStats)], key: SortKey, reverse: bool) {
+    authors.sort_by(|(a_name, a), (b_name, b)| {
+        let ordering = match key {
+            // Weighted lines are plain line counts unless --decay, --co-authors
+            // or --per-file-weight is set
+            SortKey::Lines => b.weighted_lines().total_cmp(&a.weighted_lines()),
+            SortKey::Recent => b.last_commit_time().cmp(&a.last_commit_time()),
+            SortKey::Commits => b.commits().len().cmp(&a.commits().len()),
+            SortKey::Name => a_name.to_lowercase().cmp(&b_name.to_lowercase()),
+        };
+        // Break ties by name so output is stable across runs
+        ordering.then_with(|| a_name.cmp(b_name))
+    });
+
+    if reverse {
+        authors.reverse();
     }
+}
 
-    String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .map(|s| git_root.join(s).to_string_lossy().to_string())
-        .collect()
+fn print_json(
+    out: &mut dyn Write,
+    authors: &[(String, AuthorStats)],
+    metric: Metric,
+    total: f64,
+    date_format: Option<&DateFormat>,
+) -> io::Result<()> {
+    let entries: Vec<JsonAuthor> = authors
+        .iter()
+        .map(|(author, author_stats)| JsonAuthor {
+            author,
+            lines: author_stats.lines(),
+            percentage: share_of(metric.of(author_stats), total),
+            percentage_of: metric.name(),
+            last_commit_time: author_stats.last_commit_time(),
+            last_commit_date: match date_format {
+                Some(format) => Some(format.format(author_stats.last_commit_time())),
+                None => Utc
+                    .timestamp_opt(author_stats.last_commit_time(), 0)
+                    .single()
+                    .map(|date| date.to_rfc3339()),
+            },
+            last_commit_sha: author_stats.last_commit_sha(),
+            first_commit_time: author_stats.first_commit_time(),
+            commits: author_stats.commits().len(),
+            files: author_stats.files().len(),
+        })
+        .collect();
+
+    writeln!(out, "{}", serde_json::to_string_pretty(&entries).unwrap())?;
+    Ok(())
+}
+
+fn print_csv(
+    out: &mut dyn Write,
+    authors: &[(String, AuthorStats)],
+    metric: Metric,
+    total: f64,
+) -> io::Result<()> {
+    let percentage_column = match metric {
+        Metric::Lines => "percentage",
+        Metric::WeightedLines => "weighted_percentage",
+        Metric::FileShares => "file_percentage",
+        Metric::Commits => "commit_percentage",
+    };
+    writeln!(
+        out,
+        "author,lines,{},commits,last_touched_epoch",
+        percentage_column
+    )?;
+    for (author, author_stats) in authors {
+        let percentage = share_of(metric.of(author_stats), total);
+        writeln!(
+            out,
+            "{},{},{:.2},{},{}",
+            csv_field(author),
+            author_stats.lines(),
+            percentage,
+            author_stats.commits().len(),
+            author_stats.last_commit_time()
+        )?;
+    }
+    Ok(())
 }
 
-fn collect_blame_stats(
-    file: &str,
-    git_root: &Path,
-    stats: &mut HashMap<String, AuthorStats>,
-) -> Result<(), String> {
-    let file_path = Path::new(file);
-    let relative_file = file_path
-        .strip_prefix(git_root)
-        .unwrap_or(file_path)
-        .to_string_lossy();
+fn print_markdown(
+    out: &mut dyn Write,
+    authors: &[(String, AuthorStats)],
+    metric: Metric,
+    displayed_percentage: &dyn Fn(&str) -> f64,
+    date_format: &DateFormat,
+    logins: &HashSet<String>,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "| Author | Lines | Share{} | Commits | Last touched |",
+        metric.share_label()
+    )?;
+    writeln!(out, "| --- | ---: | ---: | ---: | --- |")?;
+    for (author, author_stats) in authors {
+        let name = if logins.contains(author) {
+            format!("@{}", author)
+        } else {
+            author.clone()
+        };
+        writeln!(
+            out,
+            "| {} | {} | {:.1}% | {} | {} |",
+            markdown_cell(&name),
+            author_stats.lines(),
+            displayed_percentage(author),
+            author_stats.commits().len(),
+            markdown_cell(&date_format.format(author_stats.last_commit_time()))
+        )?;
+    }
+    Ok(())
+}
 
-    let output = Command::new("git")
-        .args(["blame", "--line-porcelain", &*relative_file])
-        .current_dir(git_root)
-        .output()
-        .map_err(|e| format!("Failed to run git blame: {}", e))?;
+/// Escape characters that would end or break a Markdown table cell.
+fn markdown_cell(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('\n', " ")
+}
 
-    if !output.status.success() {
-        return Err(String::from_utf8_lossy(&output.stderr).to_string());
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
     }
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut current_author: Option<String> = None;
-    let mut current_time: i64 = 0;
-    let mut current_sha: Option<String> = None;
-
-    for line in stdout.lines() {
-        // First line of each block starts with 40-char SHA
-        if line.len() >= 40 && line.chars().take(40).all(|c| c.is_ascii_hexdigit()) {
-            current_sha = Some(line[..40].to_string());
-        } else if let Some(author) = line.strip_prefix("author ") {
-            current_author = Some(author.to_string());
-        } else if let Some(time_str) = line.strip_prefix("author-time ") {
-            if let Ok(time) = time_str.parse::<i64>() {
-                current_time = time;
-            }
-        } else if line.starts_with('\t') {
-            // This is the actual line content, meaning we've finished parsing this block
-            if let Some(ref author) = current_author {
-                let entry = stats.entry(author.clone()).or_default();
-                entry.lines += 1;
-                if current_time > entry.last_commit_time {
-                    entry.last_commit_time = current_time;
-                }
-                if let Some(ref sha) = current_sha {
-                    entry.commits.insert(sha.clone());
-                }
-            }
+fn matches_author(author: &str, patterns: &[String]) -> bool {
+    let author = author.to_lowercase();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => author.starts_with(prefix),
+            None => author == pattern,
         }
+    })
+}
+
+/// Well-known automation accounts, matched case-insensitively.
+const KNOWN_BOTS: &[&str] = &[
+    "dependabot",
+    "dependabot-preview",
+    "renovate",
+    "renovate-bot",
+    "github-actions",
+    "greenkeeper",
+    "snyk-bot",
+    "pre-commit-ci",
+];
+
+fn is_bot(author: &str, extra_patterns: &[Pattern]) -> bool {
+    let lower = author.to_lowercase();
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    lower.ends_with("[bot]")
+        || KNOWN_BOTS.contains(&lower.as_str())
+        || extra_patterns
+            .iter()
+            .any(|p| p.matches_with(author, options))
+}
+
+/// Parse a size in bytes, optionally with a k, M, or G suffix (binary units,
+/// so 1k is 1024 bytes).
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => n
+            .checked_mul(unit)
+            .ok_or_else(|| format!("'{}' is too large", value)),
+        _ => Err(format!(
+            "expected a size in bytes like 500000 or 5M, got '{}'",
+            value
+        )),
     }
+}
 
-    Ok(())
+/// Parse a half-life like `90d`, `12w`, `6m` (30-day months), or `1y`; a bare
+/// number is days.
+fn parse_half_life(value: &str) -> Result<Duration, String> {
+    let (number, unit_days) = match value.char_indices().last() {
+        Some((i, 'd')) => (&value[..i], 1),
+        Some((i, 'w')) => (&value[..i], 7),
+        Some((i, 'm')) => (&value[..i], 30),
+        Some((i, 'y')) => (&value[..i], 365),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Duration::from_secs(n * unit_days * 24 * 60 * 60)),
+        _ => Err(format!(
+            "expected a positive duration like 90d, got '{}'",
+            value
+        )),
+    }
+}
+
+/// Parse a `REV1..REV2` range for --compare, where an empty side means HEAD.
+fn parse_compare(value: &str) -> Result<(String, String), String> {
+    let Some((from, to)) = value.split_once("..") else {
+        return Err(format!("expected REV1..REV2, got '{}'", value));
+    };
+    if to.starts_with('.') {
+        return Err(format!(
+            "'{}' is a symmetric difference; use two dots, as in REV1..REV2",
+            value
+        ));
+    }
+    let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+    Ok((or_head(from), or_head(to)))
+}
+
+fn parse_date_format(value: &str) -> Result<DateFormat, String> {
+    let format = match value {
+        "relative" => return Ok(DateFormat::Relative),
+        "iso" => "%Y-%m-%d",
+        "short" => "%b %Y",
+        custom => custom,
+    };
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid date format '{}'", value));
+    }
+    Ok(DateFormat::Strftime(format.to_string()))
 }
 
 fn format_relative_time(timestamp: i64) -> String {
-    let dt: DateTime<Utc> = Utc.timestamp_opt(timestamp, 0).unwrap();
-    let now = Utc::now();
+    format_relative_time_at(timestamp, Utc::now())
+}
+
+/// Describe `timestamp` relative to `now`. Missing (zero) timestamps read as
+/// "unknown", and commits from a skewed clock as "in the future".
+fn format_relative_time_at(timestamp: i64, now: DateTime<Utc>) -> String {
+    let dt = match Utc.timestamp_opt(timestamp, 0).single() {
+        Some(dt) if timestamp > 0 => dt,
+        _ => return "unknown".to_string(),
+    };
     let duration = now.signed_duration_since(dt);
+    if duration.num_minutes() < -1 {
+        return "in the future".to_string();
+    }
 
     let days = duration.num_days();
     if days == 0 {
@@ -293,7 +2537,7 @@ fn format_relative_time(timestamp: i64) -> String {
             }
             return format!("{} minutes ago", minutes);
         }
-        return format!("{} hours ago", hours);
+        return format!("{} hour{} ago", hours, if hours == 1 { "" } else { "s" });
     }
     if days == 1 {
         return "yesterday".to_string();
@@ -313,112 +2557,290 @@ fn format_relative_time(timestamp: i64) -> String {
     format!("{} year{} ago", years, if years == 1 { "" } else { "s" })
 }
 
-fn get_github_repo(git_root: &Path) -> Option<(String, String)> {
-    let output = Command::new("git")
-        .args(["remote", "get-url", "origin"])
-        .current_dir(git_root)
-        .output()
-        .ok()?;
+/// Last result of the once-a-day release check behind [`nudge_if_outdated`].
+#[derive(Serialize, Deserialize)]
+struct UpdateCheck {
+    checked_at: i64,
+    latest: String,
+}
+
+const UPDATE_CHECK_INTERVAL_SECS: i64 = 24 * 60 * 60;
 
-    if !output.status.success() {
-        return None;
+fn check_update() {
+    let Some(tag) = get_latest_release_tag() else {
+        eprintln!("Could not check for updates");
+        return;
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    if is_newer_version(&tag, current) {
+        println!(
+            "blame {} is available (you have {}); run `blame --upgrade` to update",
+            tag.trim_start_matches('v'),
+            current
+        );
+    } else {
+        println!("blame {} is up to date", current);
     }
+}
 
-    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+/// Print a one-line hint when a newer release exists, hitting the network at
+/// most once a day and remembering the answer in between.
+fn nudge_if_outdated() {
+    let Some(path) = cache_dir().map(|dir| dir.join("update-check.json")) else {
+        return;
+    };
+    let now = Utc::now().timestamp();
+    let cached: Option<UpdateCheck> = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok());
 
-    // Parse GitHub URL formats:
-    // https://github.com/owner/repo.git
-    // git@github.com:owner/repo.git
-    // https://github.com/owner/repo
-    if let Some(rest) = url.strip_prefix("https://github.com/") {
-        let rest = rest.trim_end_matches(".git");
-        let parts: Vec<&str> = rest.splitn(2, '/').collect();
-        if parts.len() == 2 {
-            return Some((parts[0].to_string(), parts[1].to_string()));
-        }
-    } else if let Some(rest) = url.strip_prefix("git@github.com:") {
-        let rest = rest.trim_end_matches(".git");
-        let parts: Vec<&str> = rest.splitn(2, '/').collect();
-        if parts.len() == 2 {
-            return Some((parts[0].to_string(), parts[1].to_string()));
+    let latest = match cached {
+        Some(check) if now - check.checked_at < UPDATE_CHECK_INTERVAL_SECS => check.latest,
+        _ => {
+            // Record failures too, so being offline doesn't mean a lookup every run
+            let latest = get_latest_release_tag().unwrap_or_default();
+            let check = UpdateCheck {
+                checked_at: now,
+                latest: latest.clone(),
+            };
+            let _ = std::fs::create_dir_all(path.parent().unwrap());
+            let _ = std::fs::write(&path, serde_json::to_string(&check).unwrap());
+            latest
         }
+    };
+
+    if is_newer_version(&latest, env!("CARGO_PKG_VERSION")) {
+        eprintln!(
+            "A new version of blame ({}) is available; run `blame --upgrade`",
+            latest.trim_start_matches('v')
+        );
     }
+}
 
-    None
+/// Whether release tag `tag` (like `v1.2.3`) is newer than `current`. Tags that
+/// don't parse are never newer.
+fn is_newer_version(tag: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Option<Vec<u64>> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    match (parse(tag), parse(current)) {
+        (Some(tag), Some(current)) => tag > current,
+        _ => false,
+    }
 }
 
-fn get_github_username(owner: &str, repo: &str, sha: &str) -> Option<String> {
-    let output = Command::new("gh")
-        .args([
-            "api",
-            &format!("repos/{}/{}/commits/{}", owner, repo, sha),
-            "--jq",
-            ".author.login",
-        ])
-        .output()
-        .ok()?;
+const REPO_URL: &str = "https://github.com/flaque/blame";
 
-    if !output.status.success() {
-        return None;
+fn upgrade() {
+    println!("Upgrading blame...");
+
+    let checkout = std::env::temp_dir().join("blame-upgrade");
+    let result = update_checkout(&checkout).and_then(|()| {
+        run_step(
+            "cargo install",
+            Command::new("cargo")
+                .args(["install", "--path"])
+                .arg(&checkout),
+        )
+    });
+
+    if let Err(e) = result {
+        eprintln!("Upgrade failed: {}", e);
+        std::process::exit(1);
     }
 
-    let username = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if username.is_empty() || username == "null" {
-        None
+    // Ask the binaries themselves rather than trusting cargo's exit status
+    let previous = env!("CARGO_PKG_VERSION");
+    let installed =
+        cargo_bin_dir().map(|dir| dir.join(format!("blame{}", std::env::consts::EXE_SUFFIX)));
+    let Some(version) = installed
+        .as_deref()
+        .and_then(|path| reported_version(path.as_os_str()))
+    else {
+        eprintln!("Upgrade failed: could not run the installed blame to check its version");
+        std::process::exit(1);
+    };
+    if version == previous {
+        println!("Already up to date ({})", version);
     } else {
-        Some(username)
+        println!("Upgraded from {} to {}", previous, version);
+    }
+
+    // e.g. a package manager's blame earlier on PATH than cargo's
+    match reported_version("blame".as_ref()) {
+        Some(on_path) if on_path != version => eprintln!(
+            "Warning: `blame` on your PATH is still version {}; put {} ahead of it on PATH",
+            on_path,
+            installed
+                .as_deref()
+                .and_then(Path::parent)
+                .unwrap_or(Path::new(""))
+                .display()
+        ),
+        _ => {}
     }
 }
 
-fn resolve_github_usernames(
-    stats: HashMap<String, AuthorStats>,
-    owner: &str,
-    repo: &str,
-) -> HashMap<String, AuthorStats> {
-    let mut author_to_gh: HashMap<String, Option<String>> = HashMap::new();
-
-    // For each author, try to find their GitHub username from one of their commits
-    for (author, author_stats) in &stats {
-        if author_to_gh.contains_key(author) {
-            continue;
-        }
-        // Try the first commit to get the GitHub username
-        if let Some(sha) = author_stats.commits.iter().next() {
-            let gh_user = get_github_username(owner, repo, sha);
-            author_to_gh.insert(author.clone(), gh_user);
-        }
+/// Where `cargo install` puts binaries: $CARGO_INSTALL_ROOT/bin, else
+/// $CARGO_HOME/bin, else ~/.cargo/bin.
+fn cargo_bin_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    let root = var("CARGO_INSTALL_ROOT")
+        .or_else(|| var("CARGO_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| PathBuf::from(home).join(".cargo")))?;
+    Some(root.join("bin"))
+}
+
+/// The version `program --version` reports, such as "0.2.0" from
+/// "blame 0.2.0".
+fn reported_version(program: &std::ffi::OsStr) -> Option<String> {
+    let output = Command::new(program).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
     }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.trim().strip_prefix("blame ")?;
+    Some(version.to_string())
+}
 
-    // Rebuild stats keyed by GitHub username (or fall back to git author name)
-    let mut new_stats: HashMap<String, AuthorStats> = HashMap::new();
-    for (author, author_stats) in stats {
-        let key = author_to_gh
-            .get(&author)
-            .and_then(|u| u.clone())
-            .unwrap_or(author);
-        let entry = new_stats.entry(key).or_default();
-        entry.lines += author_stats.lines;
-        if author_stats.last_commit_time > entry.last_commit_time {
-            entry.last_commit_time = author_stats.last_commit_time;
+/// Pull the latest source into `checkout`, cloning afresh if there's no usable
+/// clone there yet.
+fn update_checkout(checkout: &Path) -> Result<(), String> {
+    if checkout.join(".git").is_dir() {
+        let pulled = run_step(
+            "git pull",
+            git_command()
+                .arg("-C")
+                .arg(checkout)
+                .args(["pull", "--ff-only"]),
+        );
+        if pulled.is_ok() {
+            return Ok(());
         }
-        entry.commits.extend(author_stats.commits);
     }
 
-    new_stats
+    if checkout.exists() {
+        std::fs::remove_dir_all(checkout)
+            .map_err(|e| format!("could not remove '{}': {}", checkout.display(), e))?;
+    }
+    run_step(
+        "git clone",
+        git_command().args(["clone", REPO_URL]).arg(checkout),
+    )
+}
+
+/// Run one upgrade step, naming it in the error if it fails.
+fn run_step(name: &str, command: &mut Command) -> Result<(), String> {
+    match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", name, status)),
+        Err(e) => Err(format!("could not run {}: {}", name, e)),
+    }
 }
 
-fn upgrade() {
-    println!("Upgrading blame...");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let status = Command::new("sh")
-        .args(["-c", "git clone https://github.com/flaque/blame /tmp/blame-upgrade 2>/dev/null || git -C /tmp/blame-upgrade pull && cargo install --path /tmp/blame-upgrade"])
-        .status();
+    /// A fixed "now" for the relative time tests: 2024-06-15 12:00:00 UTC.
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap()
+    }
 
-    match status {
-        Ok(s) if s.success() => println!("Upgrade complete!"),
-        _ => {
-            eprintln!("Upgrade failed");
-            std::process::exit(1);
-        }
+    /// `format_relative_time_at` for a time `seconds` before [`now`].
+    fn ago(seconds: i64) -> String {
+        format_relative_time_at(now().timestamp() - seconds, now())
+    }
+
+    #[test]
+    fn missing_timestamps_are_unknown() {
+        assert_eq!(format_relative_time_at(0, now()), "unknown");
+        assert_eq!(format_relative_time_at(-5, now()), "unknown");
+    }
+
+    #[test]
+    fn future_timestamps_are_not_negative() {
+        // A little clock skew still reads as just now
+        assert_eq!(ago(-30), "just now");
+        assert_eq!(ago(-3 * 60 * 60), "in the future");
+    }
+
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    #[test]
+    fn minutes_and_hours() {
+        assert_eq!(ago(59), "just now");
+        assert_eq!(ago(MINUTE), "just now");
+        assert_eq!(ago(2 * MINUTE), "2 minutes ago");
+        assert_eq!(ago(59 * MINUTE), "59 minutes ago");
+        assert_eq!(ago(60 * MINUTE), "1 hour ago");
+        assert_eq!(ago(2 * HOUR), "2 hours ago");
+        assert_eq!(ago(23 * HOUR), "23 hours ago");
+    }
+
+    #[test]
+    fn days_and_weeks() {
+        assert_eq!(ago(24 * HOUR), "yesterday");
+        assert_eq!(ago(2 * DAY - 1), "yesterday");
+        assert_eq!(ago(2 * DAY), "2 days ago");
+        assert_eq!(ago(6 * DAY), "6 days ago");
+        assert_eq!(ago(7 * DAY), "1 week ago");
+        assert_eq!(ago(14 * DAY), "2 weeks ago");
+        assert_eq!(ago(29 * DAY), "4 weeks ago");
+    }
+
+    #[test]
+    fn months_and_years() {
+        assert_eq!(ago(30 * DAY), "1 month ago");
+        assert_eq!(ago(60 * DAY), "2 months ago");
+        assert_eq!(ago(334 * DAY), "11 months ago");
+        assert_eq!(ago(364 * DAY), "12 months ago");
+        assert_eq!(ago(365 * DAY), "1 year ago");
+        assert_eq!(ago(2 * 365 * DAY), "2 years ago");
+    }
+
+    #[test]
+    fn equal_thirds_round_to_exactly_one_hundred() {
+        let third = 100.0 / 3.0;
+        let rounded = largest_remainder_round(&[third, third, third]);
+        assert_eq!(rounded, vec![33.3, 33.3, 33.4]);
+        assert_eq!(rounded.iter().sum::<f64>(), 100.0);
+    }
+
+    #[test]
+    fn zero_share_stays_zero() {
+        let rounded = largest_remainder_round(&[62.25, 37.75, 0.0]);
+        assert_eq!(rounded, vec![62.2, 37.8, 0.0]);
+        assert_eq!(rounded.iter().sum::<f64>(), 100.0);
+    }
+
+    #[test]
+    fn single_author_gets_everything() {
+        assert_eq!(largest_remainder_round(&[100.0]), vec![100.0]);
+    }
+
+    #[test]
+    fn equal_shares_are_not_concentrated() {
+        assert_eq!(gini_coefficient(&[25.0, 25.0, 25.0, 25.0]), 0.0);
+    }
+
+    #[test]
+    fn single_contributor_is_fully_concentrated() {
+        assert_eq!(gini_coefficient(&[100.0]), 1.0);
+        // Everyone else owning nothing comes to the same
+        assert_eq!(gini_coefficient(&[100.0, 0.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn no_contributors_are_not_concentrated() {
+        assert_eq!(gini_coefficient(&[]), 0.0);
     }
 }
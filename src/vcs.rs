@@ -0,0 +1,403 @@
+//! Version control systems that can be blamed.
+//!
+//! Each VCS implements [`VcsBackend`]; [`detect_vcs`] picks one by walking up
+//! from a path looking for `.git` or `.hg`.
+
+mod git;
+mod hg;
+#[cfg(feature = "git2")]
+mod libgit2;
+
+pub use git::GitBackend;
+pub use hg::HgBackend;
+
+use crate::{AuthorStats, BlameError, BlameOptions, comments};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A run of consecutive lines last changed by the same commit.
+pub struct BlameHunk {
+    /// Author name as recorded by the VCS.
+    pub author: String,
+    /// Author email, if the VCS recorded one.
+    pub mail: Option<String>,
+    /// Unix timestamp of the commit.
+    pub time: i64,
+    /// Commit identifier.
+    pub sha: String,
+    /// Number of lines in the hunk.
+    pub lines: usize,
+}
+
+impl BlameHunk {
+    /// Whether these lines only exist in the working copy. git reports them
+    /// under a zeroed SHA and the pseudo-author "Not Committed Yet".
+    pub fn is_uncommitted(&self) -> bool {
+        self.sha.bytes().all(|b| b == b'0')
+    }
+}
+
+/// Someone credited on a commit besides its author: a name, and an email if
+/// one was given.
+pub type CoAuthor = (String, Option<String>);
+
+/// Repository operations `blame` needs from a version control system.
+pub trait VcsBackend: Sync {
+    /// Short name used in messages, e.g. `"git"`.
+    fn name(&self) -> &'static str;
+
+    /// Find the root of the repository containing `path`.
+    fn root(&self, path: &Path) -> Option<PathBuf>;
+
+    /// Whether `path` is tracked, at `rev` if given or in the working copy.
+    fn is_tracked(&self, path: &Path, root: &Path, rev: Option<&str>) -> bool;
+
+    /// Absolute paths of every tracked file under `dir`.
+    fn tracked_files(&self, dir: &Path, root: &Path, rev: Option<&str>) -> Vec<String>;
+
+    /// Absolute roots of the repositories nested in this one, such as git
+    /// submodules, at `rev` if given or in the working copy.
+    fn submodules(&self, _root: &Path, _rev: Option<&str>) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Whether the repository has at least one commit.
+    fn has_commits(&self, root: &Path) -> bool;
+
+    /// Whether the repository only has part of its history, as after
+    /// `git clone --depth`.
+    fn is_shallow(&self, _root: &Path) -> bool {
+        false
+    }
+
+    /// Fetch the rest of a shallow repository's history.
+    fn unshallow(&self, _root: &Path) -> Result<(), BlameError> {
+        Ok(())
+    }
+
+    /// Whether `rev` names a commit.
+    fn is_valid_rev(&self, rev: &str, root: &Path) -> bool;
+
+    /// The last commit reachable from `rev` (or the current one) before `date`.
+    fn last_commit_before(&self, date: &str, rev: Option<&str>, root: &Path) -> Option<String>;
+
+    /// The subset of `shas` that are merge commits.
+    fn merge_commits(&self, shas: &[&str], root: &Path) -> HashSet<String>;
+
+    /// First line of the message of each of `shas` that can be found.
+    fn commit_subjects(&self, shas: &[&str], root: &Path) -> HashMap<String, String>;
+
+    /// Co-authors credited in each of `shas` (e.g. by `Co-authored-by:`
+    /// trailers) as name and email. Commits without any may be left out.
+    fn co_authors(&self, _shas: &[&str], _root: &Path) -> HashMap<String, Vec<CoAuthor>> {
+        HashMap::new()
+    }
+
+    /// Reject options this backend can't honor.
+    fn check_options(&self, _options: &BlameOptions) -> Result<(), BlameError> {
+        Ok(())
+    }
+
+    /// Ignore-revs file to use when none was given explicitly.
+    fn default_ignore_revs_file(&self, _root: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Blame one file, restricted to `ranges` (`start,end` line pairs, or
+    /// `:function` where supported). `options.until` is already resolved.
+    fn blame(
+        &self,
+        file: &str,
+        ranges: &[String],
+        root: &Path,
+        options: &BlameOptions,
+    ) -> Result<Vec<BlameHunk>, BlameError>;
+
+    /// Canonical names for authors known under several identities, keyed by
+    /// the name to replace.
+    fn author_aliases(
+        &self,
+        _stats: &HashMap<String, AuthorStats>,
+        _root: &Path,
+    ) -> HashMap<String, String> {
+        HashMap::new()
+    }
+}
+
+/// The directory to start looking for `path`'s repository from: `path` itself
+/// if it's a directory, otherwise the one it's in. A bare file name is in the
+/// current directory.
+pub(crate) fn search_dir(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        return Some(path.to_path_buf());
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Some(parent.to_path_buf()),
+        _ => std::env::current_dir().ok(),
+    }
+}
+
+/// Roots found by [`repository_root`], keyed by VCS name and the directory the
+/// search started from.
+type RootCache = HashMap<(&'static str, PathBuf), Option<PathBuf>>;
+static ROOTS: OnceLock<Mutex<RootCache>> = OnceLock::new();
+
+/// [`VcsBackend::root`], remembered for each directory searched from (for the
+/// life of the process), so looking up every file in a large directory only
+/// runs the VCS once.
+pub fn repository_root(vcs: &dyn VcsBackend, path: &Path) -> Option<PathBuf> {
+    let key = (vcs.name(), search_dir(path)?);
+    let roots = ROOTS.get_or_init(Default::default);
+    if let Some(root) = roots.lock().unwrap().get(&key) {
+        return root.clone();
+    }
+    let root = vcs.root(&key.1);
+    roots.lock().unwrap().insert(key, root.clone());
+    root
+}
+
+/// Pick the VCS for `path` by finding the nearest ancestor with a `.git` or
+/// `.hg` entry.
+pub fn detect_vcs(path: &Path) -> Option<Box<dyn VcsBackend>> {
+    search_dir(path)?
+        .ancestors()
+        .find_map(|dir| -> Option<Box<dyn VcsBackend>> {
+            if dir.join(".git").exists() {
+                Some(Box::new(GitBackend))
+            } else if dir.join(".hg").is_dir() {
+                Some(Box::new(HgBackend))
+            } else {
+                None
+            }
+        })
+}
+
+/// Run `command` like [`Command::output`], but kill it and fail with
+/// [`io::ErrorKind::TimedOut`] if it is still running after `timeout`.
+pub(crate) fn output_with_timeout(
+    command: &mut Command,
+    timeout: Option<Duration>,
+) -> io::Result<Output> {
+    let Some(timeout) = timeout else {
+        return command.output();
+    };
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Drain both pipes while waiting so a chatty child can't block on a full one
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let deadline = Instant::now() + timeout;
+    let mut pause = Duration::from_millis(1);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        thread::sleep(pause);
+        pause = (pause * 2).min(Duration::from_millis(50));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// How many lines of output [`stream_lines`] reads ahead of `on_line`.
+const STREAM_BUFFER_LINES: usize = 1024;
+
+/// Run `command`, handing each line of its stdout (decoded lossily, without the
+/// line ending) to `on_line` as it arrives instead of buffering all of it. Like
+/// [`output_with_timeout`], the command is killed and this fails with
+/// [`io::ErrorKind::TimedOut`] if it is still running after `timeout`. Returns
+/// the exit status and everything written to stderr.
+pub(crate) fn stream_lines(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    mut on_line: impl FnMut(&str),
+) -> io::Result<(ExitStatus, Vec<u8>)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    // Drain stderr on the side so a chatty child can't block on a full pipe
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr_pipe.read_to_end(&mut buf);
+        buf
+    });
+
+    // Lines are read on their own thread so a child that has gone quiet can
+    // still be timed out
+    let (sender, lines) = mpsc::sync_channel::<io::Result<String>>(STREAM_BUFFER_LINES);
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return,
+                Ok(_) => Ok(String::from_utf8_lossy(&line).into_owned()),
+                Err(e) => Err(e),
+            };
+            let failed = read.is_err();
+            if sender.send(read).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let received = match deadline {
+            Some(deadline) => {
+                lines.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let error = match received {
+            Ok(Ok(line)) => {
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                on_line(line.strip_suffix('\r').unwrap_or(line));
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Err(e)) => e,
+            Err(RecvTimeoutError::Timeout) => io::Error::from(io::ErrorKind::TimedOut),
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(error);
+    }
+
+    let status = child.wait()?;
+    Ok((status, stderr.join().unwrap_or_default()))
+}
+
+/// Whether each of `lines`, `file`'s contents in order, is left uncredited by
+/// [`BlameOptions::ignore_blank_lines`] or [`BlameOptions::ignore_comments`].
+/// Empty when neither is set.
+pub(crate) fn uncredited_lines(file: &str, lines: &[&str], options: &BlameOptions) -> Vec<bool> {
+    if !options.ignore_blank_lines && !options.ignore_comments {
+        return vec![];
+    }
+    let comments = comments::syntax_for(Path::new(file))
+        .filter(|_| options.ignore_comments)
+        .map(|syntax| comments::comment_lines(syntax, lines.iter().copied()))
+        .unwrap_or_default();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            (options.ignore_blank_lines && line.trim().is_empty())
+                || comments.get(index).copied().unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Take the lines [`uncredited_lines`] leaves uncredited out of `hunks`, which
+/// cover `contents` in order, dropping hunks with no lines left.
+pub(crate) fn credited_hunks(
+    hunks: Vec<BlameHunk>,
+    file: &str,
+    contents: &[&str],
+    options: &BlameOptions,
+) -> Vec<BlameHunk> {
+    let uncredited = uncredited_lines(file, contents, options);
+    if uncredited.is_empty() {
+        return hunks;
+    }
+    let mut uncredited = uncredited.into_iter();
+    hunks
+        .into_iter()
+        .filter_map(|mut hunk| {
+            let skipped = uncredited
+                .by_ref()
+                .take(hunk.lines)
+                .filter(|&skip| skip)
+                .count();
+            hunk.lines -= skipped;
+            (hunk.lines > 0).then_some(hunk)
+        })
+        .collect()
+}
+
+/// Parse a `start,end` range into 1-based inclusive line numbers.
+pub(crate) fn parse_range(range: &str) -> Result<(usize, usize), BlameError> {
+    range
+        .split_once(',')
+        .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)))
+        .ok_or_else(|| BlameError::ParseError(format!("bad line range '{}'", range)))
+}
+
+/// Number of lines in `start..start + len` that fall inside `ranges`; every
+/// line counts when there are no ranges.
+pub(crate) fn lines_in_ranges(start: usize, len: usize, ranges: &[(usize, usize)]) -> usize {
+    if ranges.is_empty() || len == 0 {
+        return len;
+    }
+    let end = start + len - 1;
+    ranges
+        .iter()
+        .map(|&(lo, hi)| (end.min(hi) + 1).saturating_sub(start.max(lo)))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunk(sha: &str, lines: usize) -> BlameHunk {
+        BlameHunk {
+            author: sha.to_string(),
+            mail: None,
+            time: 0,
+            sha: sha.to_string(),
+            lines,
+        }
+    }
+
+    #[test]
+    fn blank_lines_are_taken_out_of_multi_line_hunks() {
+        let options = BlameOptions {
+            ignore_blank_lines: true,
+            ..Default::default()
+        };
+        let contents = ["a", "", "b", "", "c"];
+        let hunks = vec![hunk("1", 3), hunk("2", 1), hunk("3", 1)];
+        let credited = credited_hunks(hunks, "file.txt", &contents, &options);
+        let lines: Vec<(&str, usize)> = credited
+            .iter()
+            .map(|hunk| (hunk.sha.as_str(), hunk.lines))
+            .collect();
+        assert_eq!(lines, [("1", 2), ("3", 1)]);
+    }
+}
@@ -0,0 +1,530 @@
+//! Git, via the `git` binary (and libgit2 for [`Backend::Git2`]).
+
+use super::{BlameHunk, CoAuthor, VcsBackend, credited_hunks, search_dir, stream_lines};
+use crate::{AuthorStats, Backend, BlameError, BlameOptions, git_command};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Blames git repositories.
+pub struct GitBackend;
+
+impl GitBackend {
+    /// Whether the `git` binary can be run at all.
+    pub fn is_available() -> bool {
+        matches!(git_command().arg("--version").output(), Ok(o) if o.status.success())
+    }
+}
+
+impl VcsBackend for GitBackend {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn root(&self, path: &Path) -> Option<PathBuf> {
+        let start_dir = search_dir(path)?;
+
+        let output = git_command()
+            .args(["rev-parse", "--show-toplevel"])
+            .current_dir(start_dir)
+            .output()
+            .ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Some(PathBuf::from(root))
+    }
+
+    fn is_tracked(&self, path: &Path, git_root: &Path, rev: Option<&str>) -> bool {
+        let relative = path.strip_prefix(git_root).unwrap_or(path);
+
+        if let Some(rev) = rev {
+            let output = git_command()
+                .args([
+                    "cat-file",
+                    "-e",
+                    &format!("{}:{}", rev, relative.to_string_lossy()),
+                ])
+                .current_dir(git_root)
+                .output();
+            return matches!(output, Ok(o) if o.status.success());
+        }
+
+        let output = git_command()
+            .args(["ls-files", "--error-unmatch", "--"])
+            .arg(relative)
+            .current_dir(git_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success())
+    }
+
+    fn tracked_files(&self, dir: &Path, git_root: &Path, rev: Option<&str>) -> Vec<String> {
+        let relative_dir = dir.strip_prefix(git_root).unwrap_or(dir);
+        let relative_dir = if relative_dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            relative_dir
+        };
+
+        // NUL-separated output with quotepath off so unusual and non-ASCII paths
+        // come through verbatim instead of C-quoted. At a specific revision the
+        // index is irrelevant, so list that commit's tree instead. Checked out
+        // submodules are listed file by file; others as a single entry.
+        let mut command = git_command();
+        command.args(["-c", "core.quotepath=false"]);
+        match rev {
+            Some(rev) => command.args(["ls-tree", "-r", "--name-only", "-z", rev, "--"]),
+            None => command.args(["ls-files", "-z", "--recurse-submodules", "--"]),
+        };
+        let output = command
+            .arg(relative_dir)
+            .current_dir(git_root)
+            .output()
+            .expect("Failed to run git ls-files");
+
+        if !output.status.success() {
+            return vec![];
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|s| !s.is_empty())
+            .map(|s| git_root.join(s).to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn submodules(&self, git_root: &Path, rev: Option<&str>) -> Vec<PathBuf> {
+        // Submodules are entries with the gitlink mode, 160000
+        let mut command = git_command();
+        command.args(["-c", "core.quotepath=false"]);
+        match rev {
+            Some(rev) => command.args(["ls-tree", "-r", "-z", rev]),
+            None => command.args(["ls-files", "-z", "--stage"]),
+        };
+        let output = command.current_dir(git_root).output();
+
+        match output {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .split('\0')
+                .filter_map(|entry| {
+                    let (info, path) = entry.split_once('\t')?;
+                    info.starts_with("160000 ").then(|| git_root.join(path))
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn has_commits(&self, git_root: &Path) -> bool {
+        self.is_valid_rev("HEAD", git_root)
+    }
+
+    fn is_shallow(&self, git_root: &Path) -> bool {
+        let output = git_command()
+            .args(["rev-parse", "--is-shallow-repository"])
+            .current_dir(git_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success() && o.stdout.trim_ascii() == b"true")
+    }
+
+    fn unshallow(&self, git_root: &Path) -> Result<(), BlameError> {
+        let output = git_command()
+            .args(["fetch", "--unshallow", "--quiet"])
+            .current_dir(git_root)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(BlameError::UnshallowFailed {
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn is_valid_rev(&self, rev: &str, git_root: &Path) -> bool {
+        let output = git_command()
+            .args([
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", rev),
+            ])
+            .current_dir(git_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success())
+    }
+
+    fn last_commit_before(&self, date: &str, rev: Option<&str>, git_root: &Path) -> Option<String> {
+        let output = git_command()
+            .args([
+                "rev-list",
+                "-1",
+                &format!("--before={}", date),
+                rev.unwrap_or("HEAD"),
+            ])
+            .current_dir(git_root)
+            .output()
+            .ok()?;
+
+        let sha = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || sha.is_empty() {
+            return None;
+        }
+        Some(sha)
+    }
+
+    fn merge_commits(&self, shas: &[&str], git_root: &Path) -> HashSet<String> {
+        if shas.is_empty() {
+            return HashSet::new();
+        }
+
+        let child = git_command()
+            .args(["rev-list", "--no-walk", "--merges", "--stdin"])
+            .current_dir(git_root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return HashSet::new();
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(shas.join("\n").as_bytes());
+        }
+
+        match child.wait_with_output() {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(String::from)
+                .collect(),
+            _ => HashSet::new(),
+        }
+    }
+
+    fn commit_subjects(&self, shas: &[&str], git_root: &Path) -> HashMap<String, String> {
+        if shas.is_empty() {
+            return HashMap::new();
+        }
+
+        let child = git_command()
+            .args(["log", "--no-walk=unsorted", "--format=%H %s", "--stdin"])
+            .current_dir(git_root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return HashMap::new();
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(shas.join("\n").as_bytes());
+        }
+
+        match child.wait_with_output() {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .map(|(sha, subject)| (sha.to_string(), subject.to_string()))
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    fn co_authors(&self, shas: &[&str], git_root: &Path) -> HashMap<String, Vec<CoAuthor>> {
+        if shas.is_empty() {
+            return HashMap::new();
+        }
+
+        // One line per commit: the SHA, a NUL, then each trailer value
+        // separated by the ASCII unit separator
+        let child = git_command()
+            .args([
+                "log",
+                "--no-walk=unsorted",
+                "--format=%H%x00%(trailers:key=Co-authored-by,valueonly,separator=%x1f)",
+                "--stdin",
+            ])
+            .current_dir(git_root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return HashMap::new();
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(shas.join("\n").as_bytes());
+        }
+
+        let output = match child.wait_with_output() {
+            Ok(output) if output.status.success() => output,
+            _ => return HashMap::new(),
+        };
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('\0'))
+            .filter(|(_, trailers)| !trailers.is_empty())
+            .map(|(sha, trailers)| {
+                let co_authors = trailers
+                    .split('\x1f')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(|value| match value.rsplit_once(" <") {
+                        Some((name, mail)) => (
+                            name.trim().to_string(),
+                            Some(mail.trim_end_matches('>').to_string()),
+                        ),
+                        None => (value.to_string(), None),
+                    })
+                    .collect();
+                (sha.to_string(), co_authors)
+            })
+            .collect()
+    }
+
+    fn check_options(&self, options: &BlameOptions) -> Result<(), BlameError> {
+        match options.backend {
+            Backend::Subprocess => Ok(()),
+            #[cfg(feature = "git2")]
+            Backend::Git2 => super::libgit2::check_options(options),
+        }
+    }
+
+    fn default_ignore_revs_file(&self, git_root: &Path) -> Option<PathBuf> {
+        Some(git_root.join(".git-blame-ignore-revs")).filter(|path| path.is_file())
+    }
+
+    fn blame(
+        &self,
+        file: &str,
+        ranges: &[String],
+        git_root: &Path,
+        options: &BlameOptions,
+    ) -> Result<Vec<BlameHunk>, BlameError> {
+        match options.backend {
+            Backend::Subprocess => blame_with_subprocess(file, ranges, git_root, options),
+            #[cfg(feature = "git2")]
+            Backend::Git2 => super::libgit2::blame(file, ranges, git_root, options),
+        }
+    }
+
+    /// Canonicalize author names through `.mailmap`. `git blame` already applies
+    /// the mailmap to porcelain output, but this also catches identities it missed.
+    fn author_aliases(
+        &self,
+        stats: &HashMap<String, AuthorStats>,
+        git_root: &Path,
+    ) -> HashMap<String, String> {
+        let mut identities: Vec<String> = stats
+            .iter()
+            .flat_map(|(author, s)| {
+                s.emails()
+                    .iter()
+                    .map(move |e| format!("{} <{}>", author, e))
+            })
+            .collect();
+        identities.sort();
+
+        if identities.is_empty() {
+            return HashMap::new();
+        }
+
+        // On stdin, as there may be too many identities for the command line
+        let child = git_command()
+            .args(["check-mailmap", "--stdin"])
+            .current_dir(git_root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            return HashMap::new();
+        };
+        // Written from another thread so a full stdout pipe can't stall both sides
+        let writer = child.stdin.take().map(|mut stdin| {
+            let input = identities.join("\n") + "\n";
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(input.as_bytes());
+            })
+        });
+        let output = child.wait_with_output();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+
+        let output = match output {
+            Ok(o) if o.status.success() => o,
+            _ => return HashMap::new(),
+        };
+
+        // check-mailmap prints one canonical identity per input, in order
+        let mut canonical: HashMap<String, String> = HashMap::new();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for (identity, mapped) in identities.iter().zip(stdout.lines()) {
+            let (author, _) = identity.rsplit_once(" <").unwrap();
+            let mapped_name = mapped.rsplit_once(" <").map_or(mapped, |(n, _)| n);
+            if mapped_name != author && !canonical.contains_key(author) {
+                canonical.insert(author.to_string(), mapped_name.to_string());
+            }
+        }
+
+        canonical
+    }
+}
+
+/// `HEAD`, if `file` has been deleted from the working tree since it was
+/// listed, so it can still be blamed as last committed.
+pub(super) fn head_if_deleted(file: &Path) -> Option<&'static str> {
+    (!file.exists()).then_some("HEAD")
+}
+
+/// Run `git blame --line-porcelain` and turn each blamed line into a hunk.
+fn blame_with_subprocess(
+    file: &str,
+    ranges: &[String],
+    git_root: &Path,
+    options: &BlameOptions,
+) -> Result<Vec<BlameHunk>, BlameError> {
+    let file_path = Path::new(file);
+    let relative_file = file_path
+        .strip_prefix(git_root)
+        .unwrap_or(file_path)
+        .to_string_lossy();
+
+    let mut blame_args = vec!["blame".to_string(), "--line-porcelain".to_string()];
+    if let Some(since) = &options.since {
+        blame_args.push(format!("--since={}", since));
+    }
+    if options.ignore_whitespace {
+        blame_args.push("-w".to_string());
+    }
+    if options.follow {
+        blame_args.extend(["-C", "-C", "-M"].map(String::from));
+    }
+    if options.first_parent {
+        blame_args.push("--first-parent".to_string());
+    }
+    if let Some(path) = &options.ignore_revs_file {
+        blame_args.push("--ignore-revs-file".to_string());
+        blame_args.push(path.to_string_lossy().to_string());
+    }
+    for range in ranges {
+        blame_args.push("-L".to_string());
+        blame_args.push(range.clone());
+    }
+    if let Some(rev) = options.rev.as_deref().or(head_if_deleted(file_path)) {
+        blame_args.push(rev.to_string());
+    }
+    blame_args.push("--".to_string());
+    blame_args.push(relative_file.to_string());
+
+    let mut hunks: Vec<BlameHunk> = Vec::new();
+    let mut current_author: Option<String> = None;
+    let mut current_mail: Option<String> = None;
+    let mut current_time: i64 = 0;
+    let mut current_sha = String::new();
+    // Line number in the file of the current block, and one past the last
+    // line of the hunk being built
+    let mut current_line: usize = 0;
+    let mut next_line: usize = 0;
+    let (name_prefix, mail_prefix, time_prefix) = if options.committer {
+        ("committer ", "committer-mail ", "committer-time ")
+    } else {
+        ("author ", "author-mail ", "author-time ")
+    };
+
+    // Line contents are only needed to leave blank lines or comments out
+    let keep_contents = options.ignore_blank_lines || options.ignore_comments;
+    let mut contents: Vec<String> = Vec::new();
+    let mut parse_error: Option<BlameError> = None;
+
+    // Parse blocks as git prints them rather than holding the whole output,
+    // which runs to several times the size of the file
+    let mut command = git_command();
+    command.args(&blame_args).current_dir(git_root);
+    let (status, stderr) = stream_lines(&mut command, options.timeout, |line| {
+        if parse_error.is_some() {
+            return;
+        }
+        // First line of each block is "<sha> <original line> <final line>";
+        // the commit's details repeat on every block, so they are only copied
+        // when they change
+        if line.len() >= 40 && line.chars().take(40).all(|c| c.is_ascii_hexdigit()) {
+            if current_sha != line[..40] {
+                current_sha = line[..40].to_string();
+            }
+            current_line = line
+                .split(' ')
+                .nth(2)
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+        } else if let Some(author) = line.strip_prefix(name_prefix) {
+            if current_author.as_deref() != Some(author) {
+                current_author = Some(author.to_string());
+            }
+        } else if let Some(mail) = line.strip_prefix(mail_prefix) {
+            let mail = mail.trim_start_matches('<').trim_end_matches('>');
+            if current_mail.as_deref() != Some(mail) {
+                current_mail = Some(mail.to_string());
+            }
+        } else if let Some(time_str) = line.strip_prefix(time_prefix) {
+            match time_str.parse() {
+                Ok(time) => current_time = time,
+                Err(_) => {
+                    parse_error = Some(BlameError::ParseError(format!(
+                        "bad {}'{}'",
+                        time_prefix, time_str
+                    )))
+                }
+            }
+        } else if let Some(content) = line.strip_prefix('\t') {
+            // This is the actual line content, meaning we've finished parsing
+            // this block: it extends the last hunk if it's the next line of
+            // the same commit
+            if let Some(ref author) = current_author {
+                match hunks.last_mut() {
+                    Some(hunk) if hunk.sha == current_sha && current_line == next_line => {
+                        hunk.lines += 1
+                    }
+                    _ => hunks.push(BlameHunk {
+                        author: author.clone(),
+                        mail: current_mail.clone(),
+                        time: current_time,
+                        sha: current_sha.clone(),
+                        lines: 1,
+                    }),
+                }
+                next_line = current_line + 1;
+                if keep_contents {
+                    contents.push(content.to_string());
+                }
+            }
+        }
+    })
+    .map_err(|e| match (e.kind(), options.timeout) {
+        (io::ErrorKind::TimedOut, Some(timeout)) => BlameError::TimedOut(timeout),
+        _ => e.into(),
+    })?;
+
+    if !status.success() {
+        if options.rev.is_none() && !file_path.exists() {
+            return Err(BlameError::FileRemoved);
+        }
+        return Err(BlameError::BlameFailed {
+            stderr: String::from_utf8_lossy(&stderr).trim().to_string(),
+        });
+    }
+    if let Some(e) = parse_error {
+        return Err(e);
+    }
+
+    let contents: Vec<&str> = contents.iter().map(String::as_str).collect();
+    Ok(credited_hunks(hunks, file, &contents, options))
+}
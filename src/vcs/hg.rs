@@ -0,0 +1,291 @@
+//! Mercurial, via the `hg` binary.
+
+use super::{
+    BlameHunk, VcsBackend, credited_hunks, lines_in_ranges, output_with_timeout, parse_range,
+    search_dir,
+};
+use crate::{Backend, BlameError, BlameOptions};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// One line per annotated line: node, `hgdate` ("<unix time> <offset>"), author
+/// name, author email, and the line itself without surrounding whitespace,
+/// separated by tabs.
+const ANNOTATE_TEMPLATE: &str =
+    "{lines % '{node}\\t{date|hgdate}\\t{user|person}\\t{user|email}\\t{strip(line)}\\n'}";
+
+/// Blames Mercurial repositories.
+pub struct HgBackend;
+
+impl HgBackend {
+    /// Whether the `hg` binary can be run at all.
+    pub fn is_available() -> bool {
+        matches!(Command::new("hg").arg("--version").output(), Ok(o) if o.status.success())
+    }
+}
+
+/// `hg` with user configuration that could change output (aliases, plain mode
+/// defaults) turned off.
+fn hg() -> Command {
+    let mut command = Command::new("hg");
+    command.env("HGPLAIN", "1");
+    command
+}
+
+impl VcsBackend for HgBackend {
+    fn name(&self) -> &'static str {
+        "hg"
+    }
+
+    fn root(&self, path: &Path) -> Option<PathBuf> {
+        let start_dir = search_dir(path)?;
+
+        let output = hg().arg("root").current_dir(start_dir).output().ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Some(PathBuf::from(root))
+    }
+
+    fn is_tracked(&self, path: &Path, hg_root: &Path, rev: Option<&str>) -> bool {
+        let relative = path.strip_prefix(hg_root).unwrap_or(path);
+
+        let mut command = hg();
+        command.arg("files");
+        if let Some(rev) = rev {
+            command.args(["-r", rev]);
+        }
+        let output = command
+            .arg("--")
+            .arg(format!("path:{}", relative.to_string_lossy()))
+            .current_dir(hg_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success())
+    }
+
+    fn tracked_files(&self, dir: &Path, hg_root: &Path, rev: Option<&str>) -> Vec<String> {
+        let relative_dir = dir.strip_prefix(hg_root).unwrap_or(dir);
+
+        let mut command = hg();
+        command.args(["files", "-0"]);
+        if let Some(rev) = rev {
+            command.args(["-r", rev]);
+        }
+        let output = command
+            .arg("--")
+            .arg(format!("path:{}", relative_dir.to_string_lossy()))
+            .current_dir(hg_root)
+            .output();
+
+        let Ok(output) = output else {
+            return vec![];
+        };
+        if !output.status.success() {
+            return vec![];
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|s| !s.is_empty())
+            .map(|s| hg_root.join(s).to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn has_commits(&self, hg_root: &Path) -> bool {
+        // `.` resolves to the null revision in an empty repository, so list
+        // any changeset at all instead
+        let output = hg()
+            .args(["log", "-l", "1", "-T", "{node}"])
+            .current_dir(hg_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success() && !o.stdout.is_empty())
+    }
+
+    fn is_valid_rev(&self, rev: &str, hg_root: &Path) -> bool {
+        let output = hg()
+            .args(["log", "-l", "1", "-T", "{node}", "-r", rev])
+            .current_dir(hg_root)
+            .output();
+
+        matches!(output, Ok(o) if o.status.success() && !o.stdout.is_empty())
+    }
+
+    fn last_commit_before(&self, date: &str, rev: Option<&str>, hg_root: &Path) -> Option<String> {
+        let revset = format!("last(::({}) and date('<{}'))", rev.unwrap_or("."), date);
+        let output = hg()
+            .args(["log", "-T", "{node}", "-r", &revset])
+            .current_dir(hg_root)
+            .output()
+            .ok()?;
+
+        let node = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || node.is_empty() {
+            return None;
+        }
+        Some(node)
+    }
+
+    fn merge_commits(&self, shas: &[&str], hg_root: &Path) -> HashSet<String> {
+        if shas.is_empty() {
+            return HashSet::new();
+        }
+
+        let ids: Vec<String> = shas.iter().map(|sha| format!("id({})", sha)).collect();
+        let revset = format!("merge() and ({})", ids.join(" or "));
+        let output = hg()
+            .args(["log", "-T", "{node}\\n", "-r", &revset])
+            .current_dir(hg_root)
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(String::from)
+                .collect(),
+            _ => HashSet::new(),
+        }
+    }
+
+    fn commit_subjects(&self, shas: &[&str], hg_root: &Path) -> HashMap<String, String> {
+        if shas.is_empty() {
+            return HashMap::new();
+        }
+
+        let ids: Vec<String> = shas.iter().map(|sha| format!("id({})", sha)).collect();
+        let output = hg()
+            .args([
+                "log",
+                "-T",
+                "{node} {desc|firstline}\\n",
+                "-r",
+                &ids.join(" or "),
+            ])
+            .current_dir(hg_root)
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .map(|(node, subject)| (node.to_string(), subject.to_string()))
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    fn check_options(&self, options: &BlameOptions) -> Result<(), BlameError> {
+        let unsupported = |option| {
+            Err(BlameError::UnsupportedByBackend {
+                option,
+                backend: "hg",
+            })
+        };
+        if options.backend != Backend::Subprocess {
+            return unsupported("--backend");
+        }
+        if options.since.is_some() {
+            return unsupported("--since");
+        }
+        if options.function.is_some() {
+            return unsupported("--function");
+        }
+        if options.ignore_revs_file.is_some() {
+            return unsupported("--ignore-revs-file");
+        }
+        if options.first_parent {
+            return unsupported("--first-parent");
+        }
+        // Mercurial only records who made a changeset, not who committed it
+        if options.committer {
+            return unsupported("--committer");
+        }
+        Ok(())
+    }
+
+    fn blame(
+        &self,
+        file: &str,
+        ranges: &[String],
+        hg_root: &Path,
+        options: &BlameOptions,
+    ) -> Result<Vec<BlameHunk>, BlameError> {
+        let file_path = Path::new(file);
+        let relative_file = file_path.strip_prefix(hg_root).unwrap_or(file_path);
+
+        // `hg annotate` always follows copies and renames, so `follow` needs no flag
+        let mut command = hg();
+        command.args(["annotate", "-T", ANNOTATE_TEMPLATE]);
+        if options.ignore_whitespace {
+            command.arg("-w");
+        }
+        if let Some(rev) = &options.rev {
+            command.args(["-r", rev]);
+        }
+        command
+            .arg("--")
+            .arg(format!("path:{}", relative_file.to_string_lossy()))
+            .current_dir(hg_root);
+        let output = output_with_timeout(&mut command, options.timeout).map_err(|e| {
+            match (e.kind(), options.timeout) {
+                (io::ErrorKind::NotFound, _) => BlameError::HgNotFound,
+                (io::ErrorKind::TimedOut, Some(timeout)) => BlameError::TimedOut(timeout),
+                _ => BlameError::Io(e),
+            }
+        })?;
+
+        if !output.status.success() {
+            return Err(BlameError::BlameFailed {
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        let ranges = ranges
+            .iter()
+            .map(|range| parse_range(range))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut hunks = Vec::new();
+        let mut contents: Vec<&str> = Vec::new();
+        for (index, line) in stdout.lines().enumerate() {
+            if lines_in_ranges(index + 1, 1, &ranges) == 0 {
+                continue;
+            }
+
+            let parse_error = || BlameError::ParseError(format!("bad annotate line '{}'", line));
+            let mut fields = line.splitn(5, '\t');
+            let (Some(node), Some(date), Some(author), Some(mail), Some(content)) = (
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+                fields.next(),
+            ) else {
+                return Err(parse_error());
+            };
+            let time = date
+                .split_whitespace()
+                .next()
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(parse_error)?;
+
+            hunks.push(BlameHunk {
+                author: author.to_string(),
+                mail: Some(mail.to_string()).filter(|m| !m.is_empty()),
+                time,
+                sha: node.to_string(),
+                lines: 1,
+            });
+            contents.push(content);
+        }
+
+        Ok(credited_hunks(hunks, file, &contents, options))
+    }
+}
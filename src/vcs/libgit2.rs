@@ -0,0 +1,182 @@
+//! In-process blame via libgit2, selected with [`Backend::Git2`](crate::Backend::Git2).
+
+use super::git::head_if_deleted;
+use super::{BlameHunk, lines_in_ranges, parse_range, uncredited_lines};
+use crate::{BlameError, BlameOptions};
+use git2::{BlameOptions as Git2BlameOptions, Repository};
+use std::path::Path;
+
+/// Author name git uses for lines that only exist in the working tree.
+const NOT_COMMITTED: &str = "Not Committed Yet";
+
+/// Reject options libgit2 has no equivalent for, before any file is blamed.
+pub(crate) fn check_options(options: &BlameOptions) -> Result<(), BlameError> {
+    if options.since.is_some() {
+        return Err(BlameError::UnsupportedByBackend {
+            option: "--since",
+            backend: "git2",
+        });
+    }
+    if options.function.is_some() {
+        return Err(BlameError::UnsupportedByBackend {
+            option: "--function",
+            backend: "git2",
+        });
+    }
+    if options.ignore_revs_file.is_some() {
+        return Err(BlameError::UnsupportedByBackend {
+            option: "--ignore-revs-file",
+            backend: "git2",
+        });
+    }
+    Ok(())
+}
+
+/// Blame one file like the subprocess backend, using libgit2 instead of the
+/// `git` binary. `ranges` are `start,end` pairs as produced by
+/// [`crate::expand_pattern`].
+pub(crate) fn blame(
+    file: &str,
+    ranges: &[String],
+    git_root: &Path,
+    options: &BlameOptions,
+) -> Result<Vec<BlameHunk>, BlameError> {
+    let repo = Repository::open(git_root).map_err(blame_failed)?;
+    let file_path = Path::new(file);
+    let relative_file = file_path.strip_prefix(git_root).unwrap_or(file_path);
+
+    let mut blame_options = Git2BlameOptions::new();
+    blame_options
+        .use_mailmap(true)
+        .ignore_whitespace(options.ignore_whitespace)
+        .first_parent(options.first_parent);
+    if options.follow {
+        blame_options
+            .track_copies_same_file(true)
+            .track_copies_same_commit_moves(true)
+            .track_copies_same_commit_copies(true)
+            .track_copies_any_commit_copies(true);
+    }
+    let rev = options.rev.as_deref().or(head_if_deleted(file_path));
+    if let Some(rev) = rev {
+        let commit = repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|_| BlameError::UnknownRevision(rev.to_string()))?;
+        blame_options.newest_commit(commit.id());
+    }
+
+    let blame = repo
+        .blame_file(relative_file, Some(&mut blame_options))
+        .map_err(|e| {
+            if options.rev.is_none() && !file_path.exists() {
+                BlameError::FileRemoved
+            } else {
+                blame_failed(e)
+            }
+        })?;
+    // Without a revision, blame the working tree copy so uncommitted lines show
+    // up the same way they do with `git blame`
+    let (blame, contents) = match rev {
+        Some(rev) => {
+            let contents = if options.ignore_blank_lines || options.ignore_comments {
+                let spec = format!("{}:{}", rev, relative_file.to_string_lossy());
+                let object = repo.revparse_single(&spec).map_err(blame_failed)?;
+                let blob = object.peel_to_blob().map_err(blame_failed)?;
+                blob.content().to_vec()
+            } else {
+                vec![]
+            };
+            (blame, contents)
+        }
+        None => {
+            let contents = std::fs::read(file_path).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => BlameError::FileRemoved,
+                _ => BlameError::Io(e),
+            })?;
+            (
+                blame.blame_buffer(&contents).map_err(blame_failed)?,
+                contents,
+            )
+        }
+    };
+    let contents = String::from_utf8_lossy(&contents);
+    let contents: Vec<&str> = contents.lines().collect();
+    let uncredited = uncredited_lines(file, &contents, options);
+
+    let mailmap = repo.mailmap().map_err(blame_failed)?;
+    let ranges = ranges
+        .iter()
+        .map(|range| parse_range(range))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut hunks = Vec::new();
+    for hunk in blame.iter() {
+        let start = hunk.final_start_line();
+        let lines = if !uncredited.is_empty() {
+            (start..start + hunk.lines_in_hunk())
+                .filter(|&n| {
+                    lines_in_ranges(n, 1, &ranges) == 1
+                        && !uncredited.get(n - 1).copied().unwrap_or(false)
+                })
+                .count()
+        } else {
+            lines_in_ranges(start, hunk.lines_in_hunk(), &ranges)
+        };
+        if lines == 0 {
+            continue;
+        }
+
+        let commit_id = hunk.final_commit_id();
+        // Hunks split by `blame_buffer` keep their commit but drop the
+        // signature, so fall back to the commit's (mailmapped) author or committer
+        let signature = if options.committer {
+            hunk.final_committer()
+        } else {
+            hunk.final_signature()
+        };
+        let signature = match signature {
+            Some(sig) => Some(sig.to_owned()),
+            None if !commit_id.is_zero() => repo
+                .find_commit(commit_id)
+                .ok()
+                .and_then(|commit| {
+                    if options.committer {
+                        commit.committer_with_mailmap(&mailmap).ok()
+                    } else {
+                        commit.author_with_mailmap(&mailmap).ok()
+                    }
+                })
+                .map(|sig| sig.to_owned()),
+            None => None,
+        };
+        let (author, mail, time) = match &signature {
+            Some(sig) if !commit_id.is_zero() => (
+                String::from_utf8_lossy(sig.name_bytes()).to_string(),
+                Some(String::from_utf8_lossy(sig.email_bytes()).to_string()),
+                sig.when().seconds(),
+            ),
+            _ => (
+                NOT_COMMITTED.to_string(),
+                None,
+                chrono::Utc::now().timestamp(),
+            ),
+        };
+
+        hunks.push(BlameHunk {
+            author,
+            mail,
+            time,
+            sha: commit_id.to_string(),
+            lines,
+        });
+    }
+
+    Ok(hunks)
+}
+
+fn blame_failed(e: git2::Error) -> BlameError {
+    BlameError::BlameFailed {
+        stderr: e.message().to_string(),
+    }
+}
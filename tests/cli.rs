@@ -0,0 +1,336 @@
+//! End-to-end tests of the `blame` binary, run against one scratch git
+//! repository with a known history that's built the first time a test needs
+//! it.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::OnceLock;
+
+/// Run git in `repo` with user and system configuration shut out.
+fn git(repo: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .status()
+        .expect("could not run git");
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// Commit everything in `repo` as `author` at `date` (UTC midnight).
+fn commit(repo: &Path, author: &str, date: &str) {
+    let email = format!("{}@example.com", author);
+    let date = format!("{}T00:00:00+0000", date);
+    git(repo, &["add", "-A"]);
+    let status = Command::new("git")
+        .args(["commit", "--quiet", "-m", author])
+        .current_dir(repo)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", author)
+        .env("GIT_AUTHOR_EMAIL", &email)
+        .env("GIT_AUTHOR_DATE", &date)
+        .env("GIT_COMMITTER_NAME", author)
+        .env("GIT_COMMITTER_EMAIL", &email)
+        .env("GIT_COMMITTER_DATE", &date)
+        .status()
+        .expect("could not run git");
+    assert!(status.success(), "git commit failed");
+}
+
+fn write(repo: &Path, file: &str, contents: &str) {
+    std::fs::write(repo.join(file), contents).expect("could not write fixture file");
+}
+
+/// Lines long enough for git's copy detection to follow them between files.
+const MOVED_LINES: &str = "\
+the first line that gets moved into another file later on in history
+the second line that gets moved into another file later on in history
+";
+
+/// The fixture repository:
+///
+/// - alice (2024-01-01) writes `ws.txt`, `split.txt`, `old-name.txt`,
+///   `my fïle.txt`, `odd [1] {a,b} $HOME.txt` and an empty `empty.txt`.
+/// - bob (2024-02-01) reindents `ws.txt`, renames `old-name.txt` to
+///   `new-name.txt`, and moves the end of `split.txt` into `moved.txt`.
+/// - carol (2024-03-01) adds a line to `my fïle.txt` and rewrites the line of
+///   `odd [1] {a,b} $HOME.txt`.
+fn repo() -> &'static Path {
+    static REPO: OnceLock<PathBuf> = OnceLock::new();
+    REPO.get_or_init(|| {
+        let repo = std::env::temp_dir().join(format!("blame-cli-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(&repo).expect("could not create fixture repository");
+        git(&repo, &["init", "--quiet"]);
+
+        write(
+            &repo,
+            "ws.txt",
+            "fn main() {\nlet a = 1;\nlet b = 2;\nlet c = 3;\n}\n",
+        );
+        write(&repo, "split.txt", &format!("kept\n{}", MOVED_LINES));
+        write(&repo, "old-name.txt", "renamed\n");
+        write(&repo, "my fïle.txt", "one\ntwo\n");
+        write(&repo, "odd [1] {a,b} $HOME.txt", "odd\n");
+        write(&repo, "empty.txt", "");
+        commit(&repo, "alice", "2024-01-01");
+
+        write(
+            &repo,
+            "ws.txt",
+            "fn main() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n}\n",
+        );
+        git(&repo, &["mv", "old-name.txt", "new-name.txt"]);
+        write(&repo, "split.txt", "kept\n");
+        write(&repo, "moved.txt", MOVED_LINES);
+        commit(&repo, "bob", "2024-02-01");
+
+        write(&repo, "my fïle.txt", "one\ntwo\nthree\n");
+        write(&repo, "odd [1] {a,b} $HOME.txt", "odder\n");
+        commit(&repo, "carol", "2024-03-01");
+        repo
+    })
+}
+
+/// A repository of a test's own, for tests that change the working tree.
+/// It's deleted when dropped, even if the test fails.
+struct ScratchRepo(PathBuf);
+
+impl ScratchRepo {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("blame-cli-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("could not create scratch repository");
+        git(&path, &["init", "--quiet"]);
+        ScratchRepo(path)
+    }
+}
+
+impl Drop for ScratchRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run blame in the fixture repository, isolated from the user's config.
+fn blame(args: &[&str]) -> Output {
+    blame_in(repo(), args)
+}
+
+/// Run blame in `repo`, isolated from the user's config.
+fn blame_in(repo: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blame"))
+        .args(args)
+        .current_dir(repo)
+        .env("HOME", repo)
+        .env("XDG_CONFIG_HOME", repo)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .expect("could not run blame")
+}
+
+/// Run blame like [`blame`], with `input` on its standard input.
+fn blame_with_stdin(args: &[&str], input: &[u8]) -> Output {
+    let repo = repo();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_blame"))
+        .args(args)
+        .current_dir(repo)
+        .env("HOME", repo)
+        .env("XDG_CONFIG_HOME", repo)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not run blame");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input)
+        .expect("could not write to blame");
+    child.wait_with_output().expect("could not run blame")
+}
+
+/// The diff between two revisions of the fixture, without rename detection.
+fn diff(from: &str, to: &str, path: &str) -> Vec<u8> {
+    let output = Command::new("git")
+        .args(["diff", "--no-renames", from, to, "--", path])
+        .current_dir(repo())
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .expect("could not run git");
+    assert!(output.status.success(), "git diff failed");
+    output.stdout
+}
+
+/// Standard output of a blame run that must succeed.
+fn blame_ok(args: &[&str]) -> String {
+    let output = blame(args);
+    assert!(
+        output.status.success(),
+        "blame {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("output is UTF-8")
+}
+
+#[test]
+fn whitespace_changes_stay_with_the_original_author() {
+    assert_eq!(blame_ok(&["--only-name", "ws.txt"]), "bob\n");
+    assert_eq!(blame_ok(&["--only-name", "-w", "ws.txt"]), "alice\n");
+}
+
+#[test]
+fn plain_output_matches_golden_file() {
+    let output = blame_ok(&["-v", "--plain", "--date", "iso", "my fïle.txt"]);
+    assert_eq!(output, include_str!("golden/plain-verbose.txt"));
+}
+
+#[test]
+fn file_names_with_spaces_and_unicode_are_listed_and_blamed() {
+    let listed = blame_ok(&["--dry-run", "--plain", "."]);
+    assert!(
+        listed.lines().any(|line| line == "my fïle.txt"),
+        "{}",
+        listed
+    );
+    // carol only touched that file, so she shows up only if it was blamed
+    assert_eq!(
+        blame_ok(&["--only-name", "--author", "carol", "."]),
+        "carol\n"
+    );
+}
+
+#[test]
+fn follow_traces_lines_across_renames_and_moves() {
+    assert_eq!(
+        blame_ok(&["--only-name", "--follow", "new-name.txt"]),
+        "alice\n"
+    );
+    // Lines moved into another file are the mover's unless followed
+    assert_eq!(blame_ok(&["--only-name", "moved.txt"]), "bob\n");
+    assert_eq!(
+        blame_ok(&["--only-name", "--follow", "moved.txt"]),
+        "alice\n"
+    );
+}
+
+#[test]
+fn empty_files_and_filtered_out_authors_do_not_panic() {
+    let empty = blame(&["empty.txt"]);
+    assert_eq!(empty.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&empty.stderr).contains("Nothing to blame"));
+
+    let filtered = blame(&["--exclude-author", "*", "ws.txt"]);
+    assert_eq!(filtered.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&filtered.stderr).contains("filtered out"));
+    assert_eq!(
+        blame_ok(&["--json", "--exclude-author", "*", "ws.txt"]),
+        "[]\n"
+    );
+    // Nobody left to show after --author isn't an error either
+    let absent = blame_ok(&["--plain", "--author", "nobody", "ws.txt"]);
+    assert!(absent.starts_with("nobody"), "{}", absent);
+}
+
+#[test]
+fn bare_file_name_in_the_repository_root() {
+    let output = blame(&["ws.txt"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("bob"));
+}
+
+#[test]
+fn per_file_weight_counts_untracked_files_once() {
+    let scratch = ScratchRepo::new("per-file-weight");
+    write(&scratch.0, "one.txt", "one\n");
+    write(&scratch.0, "two.txt", "two\n");
+    commit(&scratch.0, "alice", "2024-01-01");
+    write(&scratch.0, "three.txt", "three\n");
+    commit(&scratch.0, "carol", "2024-03-01");
+    write(&scratch.0, "untracked.txt", &"line\n".repeat(500));
+
+    let output = blame_in(
+        &scratch.0,
+        &[
+            "--csv",
+            "-v",
+            "--per-file-weight",
+            "--include-untracked",
+            "untracked.txt",
+            "one.txt",
+            "two.txt",
+            "three.txt",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let output = String::from_utf8(output.stdout).expect("output is UTF-8");
+    let shares: Vec<(&str, &str)> = output
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            (fields[0], fields[2])
+        })
+        .collect();
+    assert_eq!(
+        shares,
+        [
+            ("alice", "50.00"),
+            ("(uncommitted)", "25.00"),
+            ("carol", "25.00")
+        ]
+    );
+}
+
+#[test]
+fn stdin_diff_blames_deleted_files_and_literal_paths() {
+    let deleted = blame_with_stdin(
+        &["--only-name", "--stdin-diff", "--rev", "HEAD~2"],
+        &diff("HEAD~2", "HEAD~1", "old-name.txt"),
+    );
+    assert!(deleted.status.success(), "{:?}", deleted);
+    assert_eq!(String::from_utf8_lossy(&deleted.stdout), "alice\n");
+
+    let odd = blame_with_stdin(
+        &["--only-name", "--stdin-diff", "--rev", "HEAD~1"],
+        &diff("HEAD~1", "HEAD", "odd [1] {a,b} $HOME.txt"),
+    );
+    assert!(odd.status.success(), "{:?}", odd);
+    assert_eq!(String::from_utf8_lossy(&odd.stdout), "alice\n");
+}
+
+#[test]
+fn stdin_diff_conflicts_with_paths_from_stdin() {
+    let output = blame_with_stdin(&["--stdin-diff", "-"], b"");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn files_deleted_from_the_working_tree_are_blamed_at_head() {
+    let scratch = ScratchRepo::new("deleted");
+    write(&scratch.0, "gone.txt", "one\ntwo\n");
+    write(&scratch.0, "kept.txt", "kept\n");
+    commit(&scratch.0, "alice", "2024-01-01");
+    std::fs::remove_file(scratch.0.join("gone.txt")).expect("could not delete file");
+
+    for args in [["--only-name", "gone.txt"], ["--only-name", "."]] {
+        let output = blame_in(&scratch.0, &args);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "alice\n");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    }
+}